@@ -10,6 +10,28 @@ pub enum Error {
     Csv(String),
     Io(String),
     UnsupportedFormat(String),
+    PartitionMismatch(String),
+    SchemaMismatch(String),
+    /// A bind parameter or template variable is missing or can not be used.
+    InvalidParameter(String),
+    /// A table location is empty, has an unsupported scheme or can not be resolved.
+    InvalidUri(String),
+    /// An option is out of its valid range.
+    InvalidConfig(String),
+    /// No delta table is mapped to the name.
+    TableNotFound(String),
+    /// A delta table is already mapped to the name.
+    TableExists(String),
+    /// A statement writes to the mapped delta table, delta tables are read-only in DuckDB.
+    ReadOnlyTable(String),
+    /// Nothing was read into the pipeline dataset of that name.
+    UnknownDataset(String),
+    /// A statement of a script failed, `index` is the zero based position of the statement.
+    Script {
+        index: usize,
+        statement: String,
+        message: String,
+    },
 }
 
 impl std::fmt::Display for Error {
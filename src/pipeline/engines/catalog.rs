@@ -0,0 +1,45 @@
+use deltalake::arrow::datatypes::SchemaRef;
+use deltalake::delta_datafusion::DataFusionMixins;
+
+use crate::error::Error;
+use crate::pipeline::sinks::delta_sink;
+use crate::pipeline::storage::StorageConfig;
+
+/// A delta table mapped to a name queries can use, see [`super::Engine::list_tables`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TableMapping {
+    pub name: String,
+    pub delta_path: String,
+}
+
+/// State of a mapped delta table at its latest version, read from the delta log.
+#[derive(Clone, Debug)]
+pub struct TableDescription {
+    pub name: String,
+    pub delta_path: String,
+    pub schema: SchemaRef,
+    pub version: i64,
+    pub partition_columns: Vec<String>,
+    pub num_files: usize,
+    /// Sum of the sizes of the data files in bytes.
+    pub size_bytes: i64,
+}
+
+pub(crate) async fn describe(
+    mapping: TableMapping,
+    storage: &StorageConfig,
+) -> Result<TableDescription, Error> {
+    let table = delta_sink::open_delta_table(&mapping.delta_path, storage).await?;
+    let snapshot = table.snapshot()?;
+    let files = snapshot.file_actions()?;
+
+    Ok(TableDescription {
+        name: mapping.name,
+        delta_path: mapping.delta_path,
+        schema: snapshot.input_schema()?,
+        version: snapshot.version(),
+        partition_columns: snapshot.metadata().partition_columns.clone(),
+        num_files: files.len(),
+        size_bytes: files.iter().map(|file| file.size).sum(),
+    })
+}
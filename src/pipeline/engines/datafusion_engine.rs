@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use deltalake::arrow::array::RecordBatch;
+use deltalake::datafusion::common::TableReference;
+use deltalake::datafusion::datasource::MemTable;
+use deltalake::datafusion::prelude::{DataFrame, SessionContext};
+use deltalake::datafusion::scalar::ScalarValue;
+use deltalake::datafusion::sql::parser::{DFParser, Statement as DFStatement};
+use deltalake::datafusion::sql::sqlparser::ast::Statement;
+use deltalake::datafusion::sql::sqlparser::dialect::GenericDialect;
+use futures::StreamExt;
+use tokio::sync::mpsc;
+
+use crate::error::Error;
+use crate::pipeline::sinks::delta_sink;
+use crate::pipeline::storage::StorageConfig;
+use crate::pipeline::uri::normalize_uri;
+
+use super::{
+    catalog, script, BatchStream, Engine, SqlParams, SqlValue, TableDescription, TableMapping,
+};
+
+/// An [`Engine`] running queries with DataFusion, the query engine deltalake is built on.
+///
+/// Mapped delta tables are opened at their latest version whenever a query references
+/// them, so no DuckDB extension has to be available. Queries use DataFusion's SQL dialect,
+/// e.g. mixed case column names have to be quoted. Mapping names are normalized like
+/// identifiers: a table mapped as `People` is queried as `people` or `People`.
+pub struct DataFusionEngine {
+    ctx: SessionContext,
+    /// Table name to delta path
+    mappings: Arc<RwLock<HashMap<String, String>>>,
+    storage: StorageConfig,
+}
+
+fn to_scalar(value: &SqlValue) -> ScalarValue {
+    match value {
+        SqlValue::Null => ScalarValue::Null,
+        SqlValue::Bool(value) => ScalarValue::Boolean(Some(*value)),
+        SqlValue::Int(value) => ScalarValue::Int64(Some(*value)),
+        SqlValue::Float(value) => ScalarValue::Float64(Some(*value)),
+        SqlValue::Text(value) => ScalarValue::Utf8(Some(value.clone())),
+        SqlValue::Date(value) => {
+            let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+            ScalarValue::Date32(Some(value.signed_duration_since(epoch).num_days() as i32))
+        }
+    }
+}
+
+fn parse_statements(sql: &str) -> Result<Vec<DFStatement>, Error> {
+    let statements = DFParser::parse_sql(sql).map_err(|e| Error::DataFusion(e.to_string()))?;
+    Ok(statements.into_iter().collect())
+}
+
+fn parse_statement(sql: &str) -> Result<DFStatement, Error> {
+    let mut statements = parse_statements(sql)?;
+    match statements.len() {
+        1 => Ok(statements.remove(0)),
+        0 => Err(Error::DataFusion("No SQL statement to execute".to_string())),
+        _ => Err(Error::DataFusion(format!(
+            "Expected a single SQL statement but got {}, scripts have to be run with execute_script",
+            statements.len()
+        ))),
+    }
+}
+
+/// The name DataFusion resolves `name` to in SQL: unquoted names are lowercased, quoted
+/// ones like `"People"` keep their case. Mappings are stored and looked up by it.
+fn table_name(name: &str) -> String {
+    TableReference::parse_str(name).table().to_string()
+}
+
+/// Plans `statement` after registering the latest version of the mapped delta tables it
+/// references. DDL statements, e.g. `CREATE VIEW`, are executed by planning them.
+async fn plan(
+    ctx: &SessionContext,
+    mappings: &RwLock<HashMap<String, String>>,
+    storage: &StorageConfig,
+    statement: DFStatement,
+) -> Result<DataFrame, Error> {
+    let references = ctx.state().resolve_table_references(&statement)?;
+    let delta_tables: Vec<(String, String)> = {
+        let mappings = mappings
+            .read()
+            .map_err(|e| Error::DataFusion(e.to_string()))?;
+        references
+            .iter()
+            .filter_map(|reference| {
+                let name = reference.table();
+                mappings
+                    .get(name)
+                    .map(|delta_path| (name.to_string(), delta_path.clone()))
+            })
+            .collect()
+    };
+
+    for (name, delta_path) in delta_tables {
+        let table = delta_sink::open_delta_table(&delta_path, storage).await?;
+        // The name is normalized already, parsing it again would lowercase quoted names
+        ctx.deregister_table(TableReference::bare(name.clone()))?;
+        ctx.register_table(TableReference::bare(name), Arc::new(table))?;
+    }
+
+    let plan = ctx.state().statement_to_plan(statement).await?;
+    Ok(ctx.execute_logical_plan(plan).await?)
+}
+
+impl DataFusionEngine {
+    pub async fn new() -> Result<Self, Error> {
+        Self::new_with_storage(&StorageConfig::default()).await
+    }
+
+    /// An engine opening delta tables with the given storage settings, see [`StorageConfig`].
+    pub async fn new_with_storage(storage: &StorageConfig) -> Result<Self, Error> {
+        Ok(Self {
+            ctx: SessionContext::new(),
+            mappings: Arc::new(RwLock::new(HashMap::new())),
+            storage: storage.clone(),
+        })
+    }
+
+    /// The session the queries run in, e.g. to register UDFs or other table providers.
+    pub fn session(&self) -> &SessionContext {
+        &self.ctx
+    }
+}
+
+#[async_trait]
+impl Engine for DataFusionEngine {
+    async fn sql(&self, query: &str) -> Result<Vec<RecordBatch>, Error> {
+        let statement = parse_statement(query)?;
+        let df = plan(&self.ctx, &self.mappings, &self.storage, statement).await?;
+        Ok(df.collect().await?)
+    }
+
+    /// Named parameters are bound as `$1`, `$2`, ..., DataFusion only supports these
+    /// numbered placeholders for positional parameters.
+    async fn sql_with_params(
+        &self,
+        query: &str,
+        params: &SqlParams,
+    ) -> Result<Vec<RecordBatch>, Error> {
+        let mut statement = parse_statement(query)?;
+        let values = match &mut statement {
+            DFStatement::Statement(statement) => params.bind(statement)?,
+            _ => match params {
+                SqlParams::Positional(values) => values.clone(),
+                SqlParams::Named(_) => {
+                    return Err(Error::InvalidParameter(
+                        "Named parameters can only be bound in SQL statements".to_string(),
+                    ))
+                }
+            },
+        };
+        let values: Vec<ScalarValue> = values.iter().map(to_scalar).collect();
+
+        let df = plan(&self.ctx, &self.mappings, &self.storage, statement).await?;
+        Ok(df.with_param_values(values)?.collect().await?)
+    }
+
+    /// The query is planned and executed on a task of the current Tokio runtime.
+    async fn sql_stream(&self, query: &str, capacity: usize) -> Result<BatchStream, Error> {
+        let statement = parse_statement(query)?;
+        let ctx = self.ctx.clone();
+        let mappings = self.mappings.clone();
+        let storage = self.storage.clone();
+
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        tokio::spawn(async move {
+            let mut batches = match plan(&ctx, &mappings, &storage, statement).await {
+                Ok(df) => match df.execute_stream().await {
+                    Ok(batches) => batches,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into())).await;
+                        return;
+                    }
+                },
+                Err(e) => {
+                    let _ = sender.send(Err(e)).await;
+                    return;
+                }
+            };
+            while let Some(batch) = batches.next().await {
+                if sender.send(batch.map_err(Error::from)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+
+    async fn script(&self, script: &str) -> Result<Vec<RecordBatch>, Error> {
+        let statements = script::split_statements(&GenericDialect {}, script)?
+            .into_iter()
+            .enumerate()
+            .map(|(index, statement)| {
+                parse_statement(&statement).map_err(|e| Error::Script {
+                    index,
+                    statement,
+                    message: e.to_string(),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut batches = vec![];
+        for (index, statement) in statements.into_iter().enumerate() {
+            let sql = statement.to_string();
+            let is_query = matches!(
+                &statement,
+                DFStatement::Statement(statement) if matches!(**statement, Statement::Query(_))
+            );
+            let to_script_error = |e: Error| Error::Script {
+                index,
+                statement: sql.clone(),
+                message: e.to_string(),
+            };
+
+            let df = plan(&self.ctx, &self.mappings, &self.storage, statement)
+                .await
+                .map_err(to_script_error)?;
+            let result = df.collect().await.map_err(|e| to_script_error(e.into()))?;
+            if is_query {
+                batches = result;
+            }
+        }
+        Ok(batches)
+    }
+
+    async fn delta_table_mapping(&self, delta_path: &str, duck_table: &str) -> Result<(), Error> {
+        let delta_path = normalize_uri(delta_path)?;
+        let mut mappings = self
+            .mappings
+            .write()
+            .map_err(|e| Error::DataFusion(e.to_string()))?;
+        mappings.insert(table_name(duck_table), delta_path);
+        Ok(())
+    }
+
+    async fn register_batches(&self, name: &str, batches: &[RecordBatch]) -> Result<(), Error> {
+        let schema = match batches.first() {
+            Some(batch) => batch.schema(),
+            None => {
+                return Err(Error::DataFusion(format!(
+                    "No record batches to register as {}",
+                    name
+                )))
+            }
+        };
+
+        let table = MemTable::try_new(schema, vec![batches.to_vec()])?;
+        self.ctx.deregister_table(name)?;
+        self.ctx.register_table(name, Arc::new(table))?;
+        Ok(())
+    }
+
+    fn storage(&self) -> &StorageConfig {
+        &self.storage
+    }
+
+    async fn list_tables(&self) -> Result<Vec<TableMapping>, Error> {
+        let mappings = self
+            .mappings
+            .read()
+            .map_err(|e| Error::DataFusion(e.to_string()))?;
+
+        let mut tables: Vec<TableMapping> = mappings
+            .iter()
+            .map(|(name, delta_path)| TableMapping {
+                name: name.clone(),
+                delta_path: delta_path.clone(),
+            })
+            .collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tables)
+    }
+
+    async fn unregister_table(&self, name: &str) -> Result<(), Error> {
+        let name = table_name(name);
+        let mut mappings = self
+            .mappings
+            .write()
+            .map_err(|e| Error::DataFusion(e.to_string()))?;
+        if mappings.remove(&name).is_none() {
+            return Err(Error::TableNotFound(name));
+        }
+        self.ctx.deregister_table(TableReference::bare(name))?;
+        Ok(())
+    }
+
+    async fn rename_mapping(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        let (name, new_name) = (table_name(name), table_name(new_name));
+        let mut mappings = self
+            .mappings
+            .write()
+            .map_err(|e| Error::DataFusion(e.to_string()))?;
+        let delta_path = match mappings.get(&name) {
+            Some(delta_path) => delta_path.clone(),
+            None => return Err(Error::TableNotFound(name)),
+        };
+        if mappings.contains_key(&new_name) {
+            return Err(Error::TableExists(new_name));
+        }
+        mappings.remove(&name);
+        mappings.insert(new_name, delta_path);
+        self.ctx.deregister_table(TableReference::bare(name))?;
+        Ok(())
+    }
+
+    /// `name` is resolved like a table name in a query, e.g. `People` finds `people`.
+    async fn describe_table(&self, name: &str) -> Result<TableDescription, Error> {
+        let name = table_name(name);
+        let delta_path = self
+            .mappings
+            .read()
+            .map_err(|e| Error::DataFusion(e.to_string()))?
+            .get(&name)
+            .cloned();
+        match delta_path {
+            Some(delta_path) => {
+                catalog::describe(TableMapping { name, delta_path }, &self.storage).await
+            }
+            None => Err(Error::TableNotFound(name)),
+        }
+    }
+}
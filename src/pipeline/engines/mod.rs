@@ -1,17 +1,148 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
 use crate::error::Error;
+use crate::pipeline::sources::options::quote_identifier;
+use crate::pipeline::storage::StorageConfig;
+use crate::pipeline::uri::normalize_uri;
 use async_trait::async_trait;
-use aws_config::BehaviorVersion;
-use deltalake::datafusion::sql::sqlparser::{ast, dialect::GenericDialect, parser::Parser};
-use duckdb::{arrow::array::RecordBatch, params, Connection};
-use regex::Regex;
+use deltalake::datafusion::sql::sqlparser::{
+    ast::Statement, dialect::DuckDbDialect, parser::Parser,
+};
+use duckdb::{
+    arrow::array::RecordBatch,
+    params, params_from_iter,
+    vtab::{arrow::ArrowVTab, arrow_recordbatch_to_query_params},
+    Arrow, Connection, OptionalExt,
+};
+use rewrite::DeltaScanRewriter;
+use tokio::sync::{mpsc, OwnedSemaphorePermit};
+
+mod catalog;
+mod datafusion_engine;
+mod params;
+mod pool;
+mod rewrite;
+mod script;
+
+pub use catalog::{TableDescription, TableMapping};
+pub use datafusion_engine::DataFusionEngine;
+pub use params::{render_template, SqlParams, SqlValue};
+pub use pool::{DuckDBPool, PoolOptions};
+
+/// Record batches of a query as they are produced, see [`Engine::sql_stream`].
+pub type BatchStream = mpsc::Receiver<Result<RecordBatch, Error>>;
+
+/// Runs `sql` on `connection` in a blocking task and sends its result batch by batch. The
+/// connection is locked until the last batch is sent or the receiver is dropped. DuckDB
+/// keeps the result of the query and converts it to Arrow one batch at a time: at most
+/// `capacity` converted batches wait in the channel, so a slow consumer pauses the
+/// conversion instead of the whole result being buffered as Arrow batches.
+pub(crate) fn stream_query(
+    connection: Arc<Mutex<Connection>>,
+    sql: String,
+    capacity: usize,
+) -> BatchStream {
+    let (sender, receiver) = mpsc::channel(capacity.max(1));
+    tokio::task::spawn_blocking(move || {
+        let sent = connection
+            .lock()
+            .map_err(|e| Error::DuckDB(format!("DuckDB connection is poisoned: {}", e)))
+            .and_then(|connection| send_query_batches(&connection, &sql, &sender));
+        if let Err(e) = sent {
+            let _ = sender.blocking_send(Err(e));
+        }
+    });
+    receiver
+}
+
+fn send_query_batches(
+    connection: &Connection,
+    sql: &str,
+    sender: &mpsc::Sender<Result<RecordBatch, Error>>,
+) -> Result<(), Error> {
+    // The query runs once, its result carries the schema of the batches
+    let mut stmt = connection.prepare(sql)?;
+    for batch in stmt.query_arrow([])? {
+        // The receiver is gone when the consumer failed, the rest is not needed
+        if sender.blocking_send(Ok(batch)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// A SQL engine of a pipeline. The methods are async so that engines can run their work
+/// without blocking the runtime. Dropping a returned future stops that work on a best
+/// effort basis, see [`DuckDB`] for what it does not stop.
 #[async_trait]
-pub trait Engine {
-    fn sql(&self, sql: &str) -> Result<Vec<RecordBatch>, Error>;
-    fn delta_table_mapping(&self, delta_path: &str, duck_table: &str) -> Result<(), Error>;
+pub trait Engine: Send + Sync {
+    async fn sql(&self, sql: &str) -> Result<Vec<RecordBatch>, Error>;
+    /// Runs a single statement with bind parameters, see [`SqlParams`].
+    async fn sql_with_params(
+        &self,
+        sql: &str,
+        params: &SqlParams,
+    ) -> Result<Vec<RecordBatch>, Error>;
+    /// Runs a single query and streams its result, holding at most `capacity` batches.
+    async fn sql_stream(&self, sql: &str, capacity: usize) -> Result<BatchStream, Error>;
+    /// Runs the statements of a script in order and returns the result of the last query.
+    async fn script(&self, script: &str) -> Result<Vec<RecordBatch>, Error>;
+    /// Makes the delta table at `delta_path` queryable as `duck_table`, replacing an earlier
+    /// mapping of the same name. The path is stored normalized, see [`normalize_uri`].
+    async fn delta_table_mapping(&self, delta_path: &str, duck_table: &str) -> Result<(), Error>;
+    /// Makes record batches queryable as the table `name`, replacing an earlier one.
+    async fn register_batches(&self, name: &str, batches: &[RecordBatch]) -> Result<(), Error>;
+    /// Mapped delta tables ordered by name.
+    async fn list_tables(&self) -> Result<Vec<TableMapping>, Error>;
+    /// Removes the mapping of `name`, the delta table itself is left as it is.
+    async fn unregister_table(&self, name: &str) -> Result<(), Error>;
+    /// Maps the delta table of `name` to `new_name`, which must not be mapped yet.
+    async fn rename_mapping(&self, name: &str, new_name: &str) -> Result<(), Error>;
+
+    /// Settings the engine reaches object stores with, the pipeline passes them on to its sinks.
+    fn storage(&self) -> &StorageConfig;
+
+    /// Reads the schema, version, partition columns and size of a mapped delta table.
+    async fn describe_table(&self, name: &str) -> Result<TableDescription, Error> {
+        let mapping = self
+            .list_tables()
+            .await?
+            .into_iter()
+            .find(|mapping| mapping.name == name)
+            .ok_or_else(|| Error::TableNotFound(name.to_string()))?;
+        catalog::describe(mapping, self.storage()).await
+    }
+}
+
+/// Set when the future waiting for blocking DuckDB work is dropped. Cancelling is best
+/// effort: a statement already running in DuckDB is not interrupted, the work only stops
+/// before collecting the next record batch or running the next statement of a script.
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Collects a query result until the work is cancelled.
+fn collect_batches(arrow_result: Arrow<'_>, cancelled: &AtomicBool) -> Vec<RecordBatch> {
+    arrow_result
+        .take_while(|_| !cancelled.load(Ordering::Relaxed))
+        .collect()
 }
 
+/// DuckDB engine, queries run on Tokio's blocking pool one at a time.
+///
+/// A dropped query future keeps the connection until the statement it was running has
+/// finished, the next query waits for it.
 pub struct DuckDB {
-    pub connection: Connection,
+    connection: Arc<Mutex<Connection>>,
+    /// Held while the engine is checked out of a [`DuckDBPool`]
+    _permit: Option<OwnedSemaphorePermit>,
+    storage: StorageConfig,
 }
 
 #[derive(Debug)]
@@ -21,56 +152,56 @@ struct DeltaMapping {
 }
 
 impl DuckDB {
-    fn strip_protocol(url: &str) -> String {
-        let re = Regex::new(r"^(https?://)").unwrap();
-        re.replace(url, "").to_string()
+    pub async fn new() -> Result<Self, Error> {
+        Self::new_with_storage(&StorageConfig::default()).await
     }
 
-    async fn query_for_setup_aws_conn() -> String {
-        let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
-        let credentials_provider = config.credentials_provider().unwrap();
-        let credentials = credentials_provider
-            .as_ref()
-            .provide_credentials()
-            .await
-            .unwrap();
-
-        let mut use_ssl = "".to_string();
-        let secret = format!("SECRET '{}',", credentials.secret_access_key());
-        let key_id = format!("KEY_ID '{}',", credentials.access_key_id());
-        let region = format!("REGION '{}',", config.region().unwrap().to_string());
-        let url_style = "URL_STYLE 'path',".to_string();
-        let endpoint = match config.endpoint_url() {
-            Some(endpoint) => {
-                let new_endpoint = Self::strip_protocol(endpoint);
-                if endpoint.starts_with("https://") {
-                    use_ssl = "USE_SSL true".to_string();
-                } else {
-                    use_ssl = "USE_SSL false".to_string();
-                }
-                format!("Endpoint '{}',", new_endpoint)
-            }
-            None => format!(""),
-        };
+    /// An in-memory database reaching object stores with the given settings, see [`StorageConfig`].
+    pub async fn new_with_storage(storage: &StorageConfig) -> Result<Self, Error> {
+        Self::open_database(None, storage).await
+    }
 
-        format!(
-            r#"
-        CREATE SECRET secret1 (
-            TYPE S3,
-            {}
-            {}
-            {}
-            {}
-            {}
-            {}
-        );
-        "#,
-            key_id, secret, region, endpoint, url_style, use_ssl
-        )
+    /// Opens a database file, created when it does not exist yet. Delta mappings are stored
+    /// in it, so tables mapped by an earlier run can be queried by name. DuckDB locks the
+    /// file, only one process can have it open at a time.
+    pub async fn open(path: &str) -> Result<Self, Error> {
+        Self::open_with_storage(path, &StorageConfig::default()).await
     }
 
-    pub async fn new() -> Result<Self, Error> {
-        let conn = match Connection::open_in_memory() {
+    pub async fn open_with_storage(path: &str, storage: &StorageConfig) -> Result<Self, Error> {
+        Self::open_database(Some(path.to_string()), storage).await
+    }
+
+    async fn open_database(path: Option<String>, storage: &StorageConfig) -> Result<Self, Error> {
+        let secrets_sql = storage.to_duckdb_secrets().await;
+        // Installing the extensions downloads them, which must not block the runtime
+        let conn = tokio::task::spawn_blocking(move || {
+            Self::open_connection(path.as_deref(), &secrets_sql)
+        })
+        .await??;
+
+        Ok(Self::from_connection(conn, None, storage))
+    }
+
+    fn from_connection(
+        connection: Connection,
+        permit: Option<OwnedSemaphorePermit>,
+        storage: &StorageConfig,
+    ) -> Self {
+        Self {
+            connection: Arc::new(Mutex::new(connection)),
+            _permit: permit,
+            storage: storage.clone(),
+        }
+    }
+
+    /// Opens the database at `path`, or an in-memory one, and sets it up for queries.
+    fn open_connection(path: Option<&str>, secrets_sql: &str) -> Result<Connection, Error> {
+        let conn = match path {
+            Some(path) => Connection::open(path),
+            None => Connection::open_in_memory(),
+        };
+        let conn = match conn {
             Ok(conn) => conn,
             Err(e) => return Err(Error::DuckDB(format!("Failed to open connection: {}", e))),
         };
@@ -84,67 +215,81 @@ impl DuckDB {
             )));
         }
 
-        if let Err(e) =
-            conn.execute_batch("CREATE TABLE delta_mapping (delta_path TEXT, duck_table TEXT)")
-        {
+        if let Err(e) = conn.register_table_function::<ArrowVTab>("arrow") {
+            return Err(Error::DuckDB(format!(
+                "Failed to register arrow table function: {}",
+                e
+            )));
+        }
+
+        if let Err(e) = conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS delta_mapping (delta_path TEXT, duck_table TEXT PRIMARY KEY)",
+        ) {
             return Err(Error::DuckDB(format!("Failed to create table: {}", e)));
         }
 
-        let aws_conn_query = Self::query_for_setup_aws_conn().await;
-        if let Err(e) = conn.execute_batch(&aws_conn_query) {
+        if let Err(e) = conn.execute_batch(secrets_sql) {
             return Err(Error::DuckDB(format!(
-                "Failed to setup AWS connection: {}",
+                "Failed to create the storage secrets: {}",
                 e
             )));
         }
 
-        Ok(Self { connection: conn })
+        Ok(conn)
     }
 
-    fn delta_table_mapping(&self, delta_path: &str, duck_table: &str) -> Result<(), Error> {
-        self.connection.execute(
-            "INSERT INTO delta_mapping (delta_path, duck_table) VALUES (?, ?)",
-            params![delta_path, duck_table],
-        )?;
-        Ok(())
-    }
+    /// Runs `work` with the connection on the blocking pool. Dropping the returned future
+    /// sets the flag `work` gets, which it checks between batches or statements.
+    async fn run_blocking<T, F>(&self, work: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Connection, &AtomicBool) -> Result<T, Error> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _cancel_on_drop = CancelOnDrop(cancelled.clone());
 
-    fn parse_sql(&self, sql: &str) -> Result<String, Error> {
-        let dialect = GenericDialect {};
-        let parser = Parser::parse_sql(&dialect, sql).unwrap();
-        let statement = parser.first().unwrap().clone();
-        let mut table_names: Vec<String> = vec![];
-
-        match statement {
-            ast::Statement::Query(query) => {
-                if let Some(select) = query.as_ref().body.as_select() {
-                    for data in select.from.iter() {
-                        let relation_string = data.relation.to_string();
-                        let table_vec: Vec<&str> = relation_string.split(' ').collect();
-                        let table_name = table_vec.first().unwrap().to_string().replace("'", "''");
-                        table_names.push(format!("\'{}\'", table_name));
-
-                        for join in &data.joins {
-                            let relation_string = join.relation.to_string();
-                            let table_vec: Vec<&str> = relation_string.split(' ').collect();
-                            let table_name =
-                                table_vec.first().unwrap().to_string().replace("'", "''");
-                            table_names.push(format!("\'{}\'", table_name));
-                        }
-                    }
-                } else {
-                    eprintln!("Expected a SELECT statement in the query.");
-                }
+        tokio::task::spawn_blocking(move || {
+            let connection = connection
+                .lock()
+                .map_err(|e| Error::DuckDB(format!("DuckDB connection is poisoned: {}", e)))?;
+            if cancelled.load(Ordering::Relaxed) {
+                return Err(Error::DuckDB("Query was cancelled".to_string()));
             }
-            _ => unimplemented!(),
+            work(&connection, &cancelled)
+        })
+        .await?
+    }
+
+    /// Maps `duck_table` to `delta_path`. Unquoted table names are matched ignoring case, so
+    /// a name that differs from a mapped one only by case is rejected.
+    fn upsert_mapping(
+        connection: &Connection,
+        delta_path: &str,
+        duck_table: &str,
+    ) -> Result<(), Error> {
+        let colliding: Option<String> = connection
+            .query_row(
+                "SELECT duck_table FROM delta_mapping WHERE lower(duck_table) = lower(?) AND duck_table <> ?",
+                params![duck_table, duck_table],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(colliding) = colliding {
+            return Err(Error::TableExists(colliding));
         }
 
-        let sql_to_check_mapping = format!(
-            "select * from delta_mapping where duck_table in ({})",
-            table_names.join(",")
-        );
+        connection.execute(
+            "INSERT INTO delta_mapping (delta_path, duck_table) VALUES (?, ?) \
+             ON CONFLICT (duck_table) DO UPDATE SET delta_path = excluded.delta_path",
+            params![delta_path, duck_table],
+        )?;
+        Ok(())
+    }
 
-        let mut stmt = self.connection.prepare(&sql_to_check_mapping)?;
+    /// Loads the delta mappings as a map of table name to delta path.
+    fn load_mappings(connection: &Connection) -> Result<HashMap<String, String>, Error> {
+        let mut stmt = connection.prepare("select delta_path, duck_table from delta_mapping")?;
         let mapping = stmt.query_map([], |row| {
             Ok(DeltaMapping {
                 delta_path: row.get(0)?,
@@ -152,46 +297,242 @@ impl DuckDB {
             })
         })?;
 
-        let mut modified_sql = sql.to_string();
-
+        let mut mappings = HashMap::new();
         for data in mapping {
-            let parsed_column = data.unwrap();
-
-            let regex = Regex::new(&format!(
-                r"\b{}\b",
-                regex::escape(&parsed_column.duck_table)
-            ))
-            .unwrap();
-            modified_sql = regex
-                .replace_all(
-                    &modified_sql,
-                    &format!("delta_scan('{}')", parsed_column.delta_path),
-                )
-                .to_string();
+            let data = data?;
+            mappings.insert(data.duck_table, data.delta_path);
+        }
+        Ok(mappings)
+    }
+
+    fn remove_mapping(connection: &Connection, duck_table: &str) -> Result<(), Error> {
+        let removed = connection.execute(
+            "DELETE FROM delta_mapping WHERE duck_table = ?",
+            params![duck_table],
+        )?;
+        if removed == 0 {
+            return Err(Error::TableNotFound(duck_table.to_string()));
+        }
+        Ok(())
+    }
+
+    fn move_mapping(
+        connection: &Connection,
+        duck_table: &str,
+        new_name: &str,
+    ) -> Result<(), Error> {
+        let mappings = Self::load_mappings(connection)?;
+        let delta_path = match mappings.get(duck_table) {
+            Some(delta_path) => delta_path,
+            None => return Err(Error::TableNotFound(duck_table.to_string())),
+        };
+        if mappings.contains_key(new_name) {
+            return Err(Error::TableExists(new_name.to_string()));
+        }
+
+        // The name is the primary key, the row is replaced instead of updated
+        connection.execute_batch("BEGIN TRANSACTION")?;
+        let renamed = Self::remove_mapping(connection, duck_table)
+            .and_then(|_| Self::upsert_mapping(connection, delta_path, new_name));
+        match renamed {
+            Ok(()) => connection.execute_batch("COMMIT")?,
+            Err(_) => connection.execute_batch("ROLLBACK")?,
+        }
+        renamed
+    }
+
+    /// Parses `sql` and rewrites references to mapped tables into `delta_scan` calls.
+    fn parse_statements(connection: &Connection, sql: &str) -> Result<Vec<Statement>, Error> {
+        let dialect = DuckDbDialect {};
+        let mut statements = Parser::parse_sql(&dialect, sql)
+            .map_err(|e| Error::DuckDB(format!("Failed to parse SQL: {}", e)))?;
+
+        let mappings = Self::load_mappings(connection)?;
+        let mut rewriter = DeltaScanRewriter::new(&mappings);
+        for statement in statements.iter_mut() {
+            rewriter.rewrite(statement)?;
         }
 
-        Ok(modified_sql)
+        Ok(statements)
+    }
+
+    fn parse_sql(connection: &Connection, sql: &str) -> Result<String, Error> {
+        Ok(Self::parse_statement(connection, sql)?.to_string())
+    }
+
+    fn parse_statement(connection: &Connection, sql: &str) -> Result<Statement, Error> {
+        let mut statements = Self::parse_statements(connection, sql)?;
+        match statements.len() {
+            1 => Ok(statements.remove(0)),
+            0 => Err(Error::DuckDB("No SQL statement to execute".to_string())),
+            _ => Err(Error::DuckDB(format!(
+                "Expected a single SQL statement but got {}, scripts have to be run with execute_script",
+                statements.len()
+            ))),
+        }
     }
 }
 
 #[async_trait]
 impl Engine for DuckDB {
-    fn sql(&self, query: &str) -> Result<Vec<RecordBatch>, Error> {
-        let parsed_sql = match self.parse_sql(query) {
-            Ok(sql) => sql,
-            Err(e) => return Err(Error::DuckDB(format!("Failed to parse SQL: {}", e))),
-        };
+    async fn sql(&self, query: &str) -> Result<Vec<RecordBatch>, Error> {
+        let query = query.to_string();
+        self.run_blocking(move |connection, cancelled| {
+            let parsed_sql = Self::parse_sql(connection, &query)?;
+            let mut stmt = connection.prepare(&parsed_sql)?;
+            let arrow_result = stmt.query_arrow([])?;
+            Ok(collect_batches(arrow_result, cancelled))
+        })
+        .await
+    }
 
-        println!("{}", parsed_sql);
+    async fn sql_with_params(
+        &self,
+        query: &str,
+        params: &SqlParams,
+    ) -> Result<Vec<RecordBatch>, Error> {
+        let query = query.to_string();
+        let params = params.clone();
+        self.run_blocking(move |connection, cancelled| {
+            let mut statement = Self::parse_statement(connection, &query)?;
+            let values = params.bind(&mut statement)?;
 
-        let mut stmt = self.connection.prepare(&parsed_sql)?;
-        let arrow_result = stmt.query_arrow([])?;
-        let batches = arrow_result.collect::<Vec<RecordBatch>>();
-        Ok(batches)
+            let mut stmt = connection.prepare(&statement.to_string())?;
+            let arrow_result = stmt.query_arrow(params_from_iter(values.iter()))?;
+            Ok(collect_batches(arrow_result, cancelled))
+        })
+        .await
     }
 
-    fn delta_table_mapping(&self, delta_path: &str, duck_table: &str) -> Result<(), Error> {
-        self.delta_table_mapping(delta_path, duck_table)?;
-        Ok(())
+    /// The query runs on the connection of the engine, so it sees the temporary tables
+    /// created by `register_batches` and scripts. Other work of the engine waits until the
+    /// last batch is taken or the stream is dropped.
+    async fn sql_stream(&self, query: &str, capacity: usize) -> Result<BatchStream, Error> {
+        let query = query.to_string();
+        let parsed_sql = self
+            .run_blocking(move |connection, _| Self::parse_sql(connection, &query))
+            .await?;
+
+        Ok(stream_query(self.connection.clone(), parsed_sql, capacity))
+    }
+
+    async fn script(&self, script: &str) -> Result<Vec<RecordBatch>, Error> {
+        let script = script.to_string();
+        self.run_blocking(move |connection, cancelled| {
+            // Every statement is parsed before the first one runs
+            let statements = script::split_statements(&DuckDbDialect {}, &script)?
+                .into_iter()
+                .enumerate()
+                .map(|(index, statement)| {
+                    Self::parse_statement(connection, &statement).map_err(|e| Error::Script {
+                        index,
+                        statement,
+                        message: e.to_string(),
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut batches = vec![];
+
+            for (index, statement) in statements.iter().enumerate() {
+                if cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                let sql = statement.to_string();
+                let to_script_error = |e: duckdb::Error| Error::Script {
+                    index,
+                    statement: sql.clone(),
+                    message: e.to_string(),
+                };
+
+                match statement {
+                    Statement::Query(_) => {
+                        let mut stmt = connection.prepare(&sql).map_err(to_script_error)?;
+                        let arrow_result = stmt.query_arrow([]).map_err(to_script_error)?;
+                        batches = collect_batches(arrow_result, cancelled);
+                    }
+                    _ => connection.execute_batch(&sql).map_err(to_script_error)?,
+                }
+            }
+
+            Ok(batches)
+        })
+        .await
+    }
+
+    async fn delta_table_mapping(&self, delta_path: &str, duck_table: &str) -> Result<(), Error> {
+        let delta_path = normalize_uri(delta_path)?;
+        let duck_table = duck_table.to_string();
+        self.run_blocking(move |connection, _| {
+            Self::upsert_mapping(connection, &delta_path, &duck_table)
+        })
+        .await
+    }
+
+    fn storage(&self) -> &StorageConfig {
+        &self.storage
+    }
+
+    async fn list_tables(&self) -> Result<Vec<TableMapping>, Error> {
+        let mappings = self
+            .run_blocking(|connection, _| Self::load_mappings(connection))
+            .await?;
+
+        let mut tables: Vec<TableMapping> = mappings
+            .into_iter()
+            .map(|(name, delta_path)| TableMapping { name, delta_path })
+            .collect();
+        tables.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tables)
+    }
+
+    async fn unregister_table(&self, name: &str) -> Result<(), Error> {
+        let name = name.to_string();
+        self.run_blocking(move |connection, _| Self::remove_mapping(connection, &name))
+            .await
+    }
+
+    async fn rename_mapping(&self, name: &str, new_name: &str) -> Result<(), Error> {
+        let name = name.to_string();
+        let new_name = new_name.to_string();
+        self.run_blocking(move |connection, _| Self::move_mapping(connection, &name, &new_name))
+            .await
+    }
+
+    /// The batches are copied into a temporary table through the `arrow` table function. Its
+    /// parameters point at the Arrow buffers and are only valid while a statement runs, so
+    /// a view can not be kept over them and the table holds its own copy of the data.
+    async fn register_batches(&self, name: &str, batches: &[RecordBatch]) -> Result<(), Error> {
+        let table = quote_identifier(name);
+        let (first, rest) = match batches.split_first() {
+            Some((first, rest)) => (first.clone(), rest.to_vec()),
+            None => {
+                return Err(Error::DuckDB(format!(
+                    "No record batches to register as {}",
+                    name
+                )))
+            }
+        };
+
+        self.run_blocking(move |connection, cancelled| {
+            connection.execute(
+                &format!(
+                    "CREATE OR REPLACE TEMP TABLE {} AS SELECT * FROM arrow(?, ?)",
+                    table
+                ),
+                arrow_recordbatch_to_query_params(first),
+            )?;
+            for batch in rest {
+                if cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+                connection.execute(
+                    &format!("INSERT INTO {} SELECT * FROM arrow(?, ?)", table),
+                    arrow_recordbatch_to_query_params(batch),
+                )?;
+            }
+            Ok(())
+        })
+        .await
     }
 }
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+
+use chrono::NaiveDate;
+use deltalake::datafusion::sql::sqlparser::ast::{visit_expressions_mut, Expr, Statement, Value};
+use duckdb::types::{ToSql, ToSqlOutput, Value as DuckValue};
+use regex::Regex;
+
+use crate::error::Error;
+
+/// A value bound to a query parameter or rendered into a SQL template.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Text(String),
+    Date(NaiveDate),
+}
+
+impl SqlValue {
+    /// Renders the value as a SQL literal, strings are quoted and escaped.
+    pub fn to_sql_literal(&self) -> Result<String, Error> {
+        let literal = match self {
+            SqlValue::Null => "NULL".to_string(),
+            SqlValue::Bool(value) => value.to_string().to_uppercase(),
+            SqlValue::Int(value) => value.to_string(),
+            SqlValue::Float(value) if value.is_finite() => format!("{:?}", value),
+            SqlValue::Float(value) => {
+                return Err(Error::InvalidParameter(format!(
+                    "{} can not be written as a SQL literal",
+                    value
+                )))
+            }
+            SqlValue::Text(value) => format!("'{}'", value.replace('\'', "''")),
+            SqlValue::Date(value) => format!("DATE '{}'", value.format("%Y-%m-%d")),
+        };
+        Ok(literal)
+    }
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
+        let value = match self {
+            SqlValue::Null => DuckValue::Null,
+            SqlValue::Bool(value) => DuckValue::Boolean(*value),
+            SqlValue::Int(value) => DuckValue::BigInt(*value),
+            SqlValue::Float(value) => DuckValue::Double(*value),
+            SqlValue::Text(value) => DuckValue::Text(value.clone()),
+            SqlValue::Date(value) => {
+                let epoch = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap_or_default();
+                DuckValue::Date32(value.signed_duration_since(epoch).num_days() as i32)
+            }
+        };
+        Ok(ToSqlOutput::Owned(value))
+    }
+}
+
+impl From<bool> for SqlValue {
+    fn from(value: bool) -> Self {
+        SqlValue::Bool(value)
+    }
+}
+
+impl From<i32> for SqlValue {
+    fn from(value: i32) -> Self {
+        SqlValue::Int(value.into())
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(value: i64) -> Self {
+        SqlValue::Int(value)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self {
+        SqlValue::Float(value)
+    }
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        SqlValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self {
+        SqlValue::Text(value)
+    }
+}
+
+impl From<NaiveDate> for SqlValue {
+    fn from(value: NaiveDate) -> Self {
+        SqlValue::Date(value)
+    }
+}
+
+/// Bind parameters of a query.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SqlParams {
+    /// Bound in order to `?` or `$1`, `$2`, ... placeholders.
+    Positional(Vec<SqlValue>),
+    /// Bound by name to `$name` placeholders.
+    Named(Vec<(String, SqlValue)>),
+}
+
+impl SqlParams {
+    pub fn positional(values: Vec<SqlValue>) -> Self {
+        SqlParams::Positional(values)
+    }
+
+    pub fn named(values: &[(&str, SqlValue)]) -> Self {
+        SqlParams::Named(
+            values
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    /// Prepares `statement` for binding and returns the values in binding order. Named
+    /// placeholders are replaced with numbered ones, so a name can be used more than once.
+    pub(crate) fn bind(&self, statement: &mut Statement) -> Result<Vec<SqlValue>, Error> {
+        let named = match self {
+            SqlParams::Positional(values) => return Ok(values.clone()),
+            SqlParams::Named(named) => named,
+        };
+
+        let mut values: Vec<SqlValue> = vec![];
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        let result = visit_expressions_mut(statement, |expr| {
+            let Expr::Value(Value::Placeholder(placeholder)) = expr else {
+                return ControlFlow::Continue(());
+            };
+            let name = match placeholder.strip_prefix('$') {
+                Some(name) if !name.starts_with(|c: char| c.is_ascii_digit()) => name,
+                _ => {
+                    return ControlFlow::Break(Error::InvalidParameter(format!(
+                        "Placeholder {} can not be bound by name",
+                        placeholder
+                    )))
+                }
+            };
+
+            let position = match positions.get(name) {
+                Some(position) => *position,
+                None => match named.iter().find(|(param, _)| param == name) {
+                    Some((param, value)) => {
+                        values.push(value.clone());
+                        positions.insert(param.as_str(), values.len());
+                        values.len()
+                    }
+                    None => {
+                        return ControlFlow::Break(Error::InvalidParameter(format!(
+                            "No value for parameter {}",
+                            placeholder
+                        )))
+                    }
+                },
+            };
+            *placeholder = format!("${}", position);
+            ControlFlow::Continue(())
+        });
+
+        match result {
+            ControlFlow::Break(error) => Err(error),
+            ControlFlow::Continue(()) => Ok(values),
+        }
+    }
+}
+
+/// Where the scan of a SQL template is, variables are only rendered in [`Context::Sql`].
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    Sql,
+    /// Inside a string literal or a quoted identifier, with its closing quote.
+    Quoted(u8),
+    LineComment,
+    /// Inside a block comment, block comments nest.
+    BlockComment(usize),
+}
+
+/// The end and the trimmed name of the `{{ name }}` variable starting at `start`.
+fn template_variable(template: &str, start: usize) -> Option<(usize, &str)> {
+    let inner = start + 2;
+    let length = template[inner..].find("}}")?;
+    Some((inner + length + 2, template[inner..inner + length].trim()))
+}
+
+/// Replaces `{{ name }}` variables of a SQL template with the quoted literal of their value,
+/// e.g. `WHERE date = {{ run_date }}`. Unknown or malformed variables are rejected.
+///
+/// Variables in comments are left as they are. A variable inside a string literal or a
+/// quoted identifier is rejected, its value is quoted already: `'{{ run_date }}'` would
+/// render as `''2024-01-01''`.
+pub fn render_template(template: &str, vars: &HashMap<&str, SqlValue>) -> Result<String, Error> {
+    let name_pattern = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+    let bytes = template.as_bytes();
+
+    let mut rendered = String::with_capacity(template.len());
+    let mut context = Context::Sql;
+    let mut last_end = 0;
+    let mut position = 0;
+    while position < bytes.len() {
+        let rest = &bytes[position..];
+        match context {
+            Context::Sql if rest.starts_with(b"{{") => {
+                let Some((end, name)) = template_variable(template, position) else {
+                    position += 2;
+                    continue;
+                };
+                if !name_pattern.is_match(name) {
+                    return Err(Error::InvalidParameter(format!(
+                        "Invalid template variable {}",
+                        &template[position..end]
+                    )));
+                }
+                let value = match vars.get(name) {
+                    Some(value) => value,
+                    None => {
+                        return Err(Error::InvalidParameter(format!(
+                            "No value for template variable {}",
+                            name
+                        )))
+                    }
+                };
+
+                rendered.push_str(&template[last_end..position]);
+                rendered.push_str(&value.to_sql_literal()?);
+                last_end = end;
+                position = end;
+                continue;
+            }
+            Context::Sql => match rest {
+                [quote @ (b'\'' | b'"'), ..] => context = Context::Quoted(*quote),
+                [b'-', b'-', ..] => context = Context::LineComment,
+                [b'/', b'*', ..] => {
+                    context = Context::BlockComment(1);
+                    position += 1;
+                }
+                _ => {}
+            },
+            Context::Quoted(_) if rest.starts_with(b"{{") => {
+                if let Some((end, name)) = template_variable(template, position) {
+                    if name_pattern.is_match(name) {
+                        return Err(Error::InvalidParameter(format!(
+                            "Template variable {} is inside quotes, its value is quoted already",
+                            &template[position..end]
+                        )));
+                    }
+                }
+            }
+            // A doubled quote escapes it, leaving and entering the quotes again
+            Context::Quoted(quote) if rest[0] == quote => context = Context::Sql,
+            Context::LineComment if rest[0] == b'\n' => context = Context::Sql,
+            Context::BlockComment(depth) if rest.starts_with(b"/*") => {
+                context = Context::BlockComment(depth + 1);
+                position += 1;
+            }
+            Context::BlockComment(depth) if rest.starts_with(b"*/") => {
+                context = match depth {
+                    1 => Context::Sql,
+                    _ => Context::BlockComment(depth - 1),
+                };
+                position += 1;
+            }
+            _ => {}
+        }
+        position += 1;
+    }
+    rendered.push_str(&template[last_end..]);
+
+    Ok(rendered)
+}
@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex};
+
+use duckdb::Connection;
+use tokio::sync::Semaphore;
+
+use crate::error::Error;
+use crate::pipeline::sources::options::quote_literal;
+use crate::pipeline::storage::StorageConfig;
+
+use super::DuckDB;
+
+/// Options of a [`DuckDBPool`].
+#[derive(Clone, Debug)]
+pub struct PoolOptions {
+    size: usize,
+    memory_limit: Option<String>,
+    threads: Option<usize>,
+    storage: StorageConfig,
+}
+
+impl Default for PoolOptions {
+    fn default() -> Self {
+        PoolOptions {
+            size: 8,
+            memory_limit: None,
+            threads: None,
+            storage: StorageConfig::default(),
+        }
+    }
+}
+
+impl PoolOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of engines that can be checked out at the same time, 8 by default.
+    pub fn size(mut self, size: usize) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Memory the database may use across all engines, e.g. `"4GB"`. DuckDB defaults to
+    /// 80% of the system memory.
+    pub fn memory_limit(mut self, memory_limit: &str) -> Self {
+        self.memory_limit = Some(memory_limit.to_string());
+        self
+    }
+
+    /// Threads the database runs queries on across all engines, DuckDB defaults to the
+    /// number of cores.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = Some(threads);
+        self
+    }
+
+    /// Settings the engines reach object stores with, see [`StorageConfig`].
+    pub fn storage(mut self, storage: StorageConfig) -> Self {
+        self.storage = storage;
+        self
+    }
+
+    /// Renders the limits as `SET` statements.
+    fn to_settings_sql(&self) -> String {
+        let mut sql = String::new();
+
+        if let Some(memory_limit) = &self.memory_limit {
+            sql.push_str(&format!(
+                "SET memory_limit = {};",
+                quote_literal(memory_limit)
+            ));
+        }
+
+        if let Some(threads) = self.threads {
+            sql.push_str(&format!("SET threads = {};", threads));
+        }
+
+        sql
+    }
+}
+
+/// One in-memory DuckDB database shared by the engines of many pipelines.
+///
+/// The extensions, the storage secrets and the `delta_mapping` table are set up once when the
+/// pool is created. [`DuckDBPool::engine`] hands out engines on connections of their own,
+/// so they share mappings and tables but keep their temporary tables apart. Cloning the
+/// pool is cheap, the clones hand out engines of the same database.
+#[derive(Clone)]
+pub struct DuckDBPool {
+    connection: Arc<Mutex<Connection>>,
+    permits: Arc<Semaphore>,
+    storage: StorageConfig,
+}
+
+impl DuckDBPool {
+    pub async fn new(options: PoolOptions) -> Result<Self, Error> {
+        if options.size == 0 {
+            return Err(Error::InvalidConfig(
+                "The pool size has to be at least 1".to_string(),
+            ));
+        }
+
+        let secrets_sql = options.storage.to_duckdb_secrets().await;
+        let settings_sql = options.to_settings_sql();
+        let connection = tokio::task::spawn_blocking(move || {
+            let conn = DuckDB::open_connection(None, &secrets_sql)?;
+            if let Err(e) = conn.execute_batch(&settings_sql) {
+                return Err(Error::DuckDB(format!(
+                    "Failed to apply pool settings: {}",
+                    e
+                )));
+            }
+            Ok(conn)
+        })
+        .await??;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            permits: Arc::new(Semaphore::new(options.size)),
+            storage: options.storage,
+        })
+    }
+
+    /// Checks out an engine, waiting while all of them are in use. The engine goes back
+    /// to the pool when it is dropped.
+    pub async fn engine(&self) -> Result<DuckDB, Error> {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| Error::DuckDB(format!("DuckDB pool is closed: {}", e)))?;
+
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|e| Error::DuckDB(format!("DuckDB connection is poisoned: {}", e)))?
+            .try_clone()?;
+
+        Ok(DuckDB::from_connection(
+            connection,
+            Some(permit),
+            &self.storage,
+        ))
+    }
+
+    /// Number of engines that can be checked out without waiting.
+    pub fn available(&self) -> usize {
+        self.permits.available_permits()
+    }
+}
@@ -0,0 +1,166 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+
+use deltalake::datafusion::sql::sqlparser::ast::{
+    Expr, FromTable, FunctionArg, FunctionArgExpr, Ident, ObjectName, Query, Statement, TableAlias,
+    TableFactor, TableFunctionArgs, TableWithJoins, Value, VisitMut, VisitorMut, With,
+};
+
+use crate::error::Error;
+
+/// Replaces references to mapped tables with `delta_scan('<delta_path>')`.
+///
+/// Only table factors are rewritten, so names in string literals, column names, aliases and
+/// the targets of `CREATE` statements are left untouched. References without an alias get
+/// the table name as alias so that qualified column names keep resolving. A common table
+/// expression named like a mapped table hides it in the query it is defined in. Statements
+/// writing to a mapped table are rejected, delta tables are read-only in DuckDB.
+pub(crate) struct DeltaScanRewriter<'a> {
+    mappings: &'a HashMap<String, String>,
+    /// Lowercased CTE names in scope, one set per enclosing query.
+    cte_scopes: Vec<HashSet<String>>,
+    /// `WITH` clauses taken out of the enclosing queries while their body is visited.
+    withs: Vec<Option<With>>,
+}
+
+impl<'a> DeltaScanRewriter<'a> {
+    /// `mappings` maps table names to delta paths.
+    pub(crate) fn new(mappings: &'a HashMap<String, String>) -> Self {
+        DeltaScanRewriter {
+            mappings,
+            cte_scopes: vec![],
+            withs: vec![],
+        }
+    }
+
+    fn delta_path(&self, name: &ObjectName) -> Option<&'a String> {
+        let ident = match name.0.as_slice() {
+            [ident] => ident,
+            _ => return None,
+        };
+
+        let lowercase = ident.value.to_lowercase();
+        if self
+            .cte_scopes
+            .iter()
+            .any(|scope| scope.contains(&lowercase))
+        {
+            return None;
+        }
+
+        match ident.quote_style {
+            // quoted identifiers are case sensitive
+            Some(_) => self.mappings.get(&ident.value),
+            None => self
+                .mappings
+                .iter()
+                .find(|(duck_table, _)| duck_table.eq_ignore_ascii_case(&ident.value))
+                .map(|(_, delta_path)| delta_path),
+        }
+    }
+
+    /// The tables written by an `INSERT`, `UPDATE`, `DELETE` or `MERGE` statement.
+    fn dml_targets(statement: &Statement) -> Vec<&ObjectName> {
+        fn relation_name(table: &TableWithJoins) -> Option<&ObjectName> {
+            match &table.relation {
+                TableFactor::Table { name, .. } => Some(name),
+                _ => None,
+            }
+        }
+
+        match statement {
+            Statement::Insert(insert) => vec![&insert.table_name],
+            Statement::Update { table, .. } => relation_name(table).into_iter().collect(),
+            Statement::Delete(delete) => {
+                let (FromTable::WithFromKeyword(from) | FromTable::WithoutKeyword(from)) =
+                    &delete.from;
+                delete
+                    .tables
+                    .iter()
+                    .chain(from.iter().filter_map(relation_name))
+                    .collect()
+            }
+            Statement::Merge {
+                table: TableFactor::Table { name, .. },
+                ..
+            } => vec![name],
+            _ => vec![],
+        }
+    }
+
+    pub(crate) fn rewrite(&mut self, statement: &mut Statement) -> Result<(), Error> {
+        if let Some(target) = Self::dml_targets(statement)
+            .into_iter()
+            .find(|name| self.delta_path(name).is_some())
+        {
+            return Err(Error::ReadOnlyTable(target.to_string()));
+        }
+
+        let _ = VisitMut::visit(statement, self);
+        Ok(())
+    }
+}
+
+impl<'a> VisitorMut for DeltaScanRewriter<'a> {
+    type Break = ();
+
+    /// Visits the CTEs of `query` one by one, each seeing the CTEs defined before it and only
+    /// itself when the `WITH` is recursive. The `WITH` is taken out of the query so that the
+    /// visit of the query does not visit it again, [`Self::post_visit_query`] puts it back.
+    fn pre_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
+        let mut scope = HashSet::new();
+        let mut with = query.with.take();
+        if let Some(with) = &mut with {
+            for cte in &mut with.cte_tables {
+                let name = cte.alias.name.value.to_lowercase();
+                if with.recursive {
+                    scope.insert(name.clone());
+                }
+                self.cte_scopes.push(scope.clone());
+                let _ = VisitMut::visit(&mut *cte.query, self);
+                self.cte_scopes.pop();
+                scope.insert(name);
+            }
+        }
+        self.withs.push(with);
+        self.cte_scopes.push(scope);
+        ControlFlow::Continue(())
+    }
+
+    fn post_visit_query(&mut self, query: &mut Query) -> ControlFlow<Self::Break> {
+        self.cte_scopes.pop();
+        query.with = self.withs.pop().flatten();
+        ControlFlow::Continue(())
+    }
+
+    fn pre_visit_table_factor(
+        &mut self,
+        table_factor: &mut TableFactor,
+    ) -> ControlFlow<Self::Break> {
+        if let TableFactor::Table {
+            name, alias, args, ..
+        } = table_factor
+        {
+            if args.is_some() {
+                return ControlFlow::Continue(());
+            }
+
+            if let Some(delta_path) = self.delta_path(name) {
+                if alias.is_none() {
+                    *alias = Some(TableAlias {
+                        name: name.0[0].clone(),
+                        columns: vec![],
+                    });
+                }
+                *name = ObjectName(vec![Ident::new("delta_scan")]);
+                *args = Some(TableFunctionArgs {
+                    args: vec![FunctionArg::Unnamed(FunctionArgExpr::Expr(Expr::Value(
+                        Value::SingleQuotedString(delta_path.clone()),
+                    )))],
+                    settings: None,
+                });
+            }
+        }
+        ControlFlow::Continue(())
+    }
+}
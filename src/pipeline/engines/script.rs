@@ -0,0 +1,78 @@
+use deltalake::datafusion::sql::sqlparser::dialect::Dialect;
+use deltalake::datafusion::sql::sqlparser::tokenizer::{Location, Token, Tokenizer};
+
+use crate::error::Error;
+
+/// Splits tokens on the semicolons into the text of the statements, skipping the parts made
+/// of whitespace and comments only. The text after the last semicolon is returned apart.
+fn split_tokens(tokens: &[Token]) -> (Vec<String>, String) {
+    let is_blank = |part: &[Token]| {
+        part.iter()
+            .all(|token| matches!(token, Token::Whitespace(_)))
+    };
+    let to_text = |part: &[Token]| {
+        part.iter()
+            .map(|token| token.to_string())
+            .collect::<String>()
+    };
+
+    let mut parts: Vec<&[Token]> = tokens.split(|token| *token == Token::SemiColon).collect();
+    let rest = match parts.pop() {
+        Some(part) if !is_blank(part) => to_text(part),
+        _ => String::new(),
+    };
+    let statements = parts
+        .into_iter()
+        .filter(|part| !is_blank(part))
+        .map(|part| to_text(part).trim().to_string())
+        .collect();
+    (statements, rest)
+}
+
+/// Byte offset of a tokenizer location in `script`.
+fn byte_offset(script: &str, location: &Location) -> usize {
+    let mut offset = 0;
+    for (number, line) in script.split_inclusive('\n').enumerate() {
+        if number as u64 + 1 == location.line {
+            let column = location.column.saturating_sub(1) as usize;
+            return offset
+                + line
+                    .char_indices()
+                    .nth(column)
+                    .map(|(index, _)| index)
+                    .unwrap_or(line.len());
+        }
+        offset += line.len();
+    }
+    script.len()
+}
+
+/// Splits a script into the text of its statements, semicolons in string literals, quoted
+/// identifiers and comments do not end a statement.
+///
+/// A script that can not be tokenized, e.g. with an unterminated string literal, fails with
+/// [`Error::Script`] pointing at the statement the error is in.
+pub(crate) fn split_statements(dialect: &dyn Dialect, script: &str) -> Result<Vec<String>, Error> {
+    match Tokenizer::new(dialect, script).tokenize() {
+        Ok(tokens) => {
+            let (mut statements, rest) = split_tokens(&tokens);
+            if !rest.is_empty() {
+                statements.push(rest.trim().to_string());
+            }
+            Ok(statements)
+        }
+        Err(e) => {
+            // The script up to the error tokenizes, its last part starts the failing statement
+            let offset = byte_offset(script, &e.location);
+            let (statements, rest) = Tokenizer::new(dialect, &script[..offset])
+                .tokenize()
+                .map(|tokens| split_tokens(&tokens))
+                .unwrap_or_default();
+            Err(Error::Script {
+                index: statements.len(),
+                statement: format!("{}{}", rest, &script[offset..]).trim().to_string(),
+                message: e.to_string(),
+            })
+        }
+    }
+}
@@ -1,90 +1,446 @@
+use std::collections::HashMap;
+
 use deltalake::{arrow::array::RecordBatch, datafusion::prelude::SessionContext};
-use engines::Engine;
-use sinks::Sinks;
-use sources::{Sources, SourcesType};
+use engines::{Engine, SqlParams, SqlValue, TableDescription, TableMapping};
+use sinks::{
+    Maintenance, MergeMetrics, MergeSpec, OptimizeMetrics, OptimizeOptions, Sinks, StreamMetrics,
+    StreamOptions, StreamSink, VacuumMetrics, VacuumOptions, WriteMode, WriteOptions,
+};
+use sources::{CsvOptions, DeltaReadOptions, JsonOptions, SourcesType};
 
 use crate::error::Error;
 
 pub mod engines;
 pub mod sinks;
 pub mod sources;
+pub mod storage;
+pub mod uri;
+
+/// Name of the dataset steps read from and write to until another one is selected
+/// with [`Pipeline::dataset`].
+pub const DEFAULT_DATASET: &str = "default";
 
 pub struct Pipeline<Exc: Engine> {
     enginee: Exc,
-    record_batches: Option<Vec<RecordBatch>>,
+    datasets: HashMap<String, Vec<RecordBatch>>,
+    active_dataset: String,
+    rejected_batches: Option<Vec<RecordBatch>>,
 }
 
 impl<Exc: Engine> Pipeline<Exc> {
     pub async fn new(enginee: Exc) -> Result<Pipeline<Exc>, Error> {
         Ok(Pipeline {
-            enginee: enginee,
-            record_batches: None,
+            enginee,
+            datasets: HashMap::new(),
+            active_dataset: DEFAULT_DATASET.to_string(),
+            rejected_batches: None,
         })
     }
 
+    /// Selects the dataset that following reads, queries and transforms produce and that
+    /// writes, merges and `register_as` consume. Other datasets are kept as they are.
+    pub fn dataset(&mut self, name: &str) -> &mut Self {
+        self.active_dataset = name.to_string();
+        self
+    }
+
+    /// Record batches of a dataset, `None` when nothing was read into it yet.
+    pub fn get_dataset(&self, name: &str) -> Option<&Vec<RecordBatch>> {
+        self.datasets.get(name)
+    }
+
+    pub fn dataset_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.datasets.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn remove_dataset(&mut self, name: &str) -> Option<Vec<RecordBatch>> {
+        self.datasets.remove(name)
+    }
+
+    fn dataset_batches(&self, name: &str) -> Result<&Vec<RecordBatch>, Error> {
+        self.datasets
+            .get(name)
+            .ok_or_else(|| Error::UnknownDataset(name.to_string()))
+    }
+
+    fn batches(&self) -> Result<&Vec<RecordBatch>, Error> {
+        self.dataset_batches(&self.active_dataset)
+    }
+
+    fn set_batches(&mut self, batches: Vec<RecordBatch>) {
+        self.datasets.insert(self.active_dataset.clone(), batches);
+    }
+
     pub async fn read_csv(&mut self, path: &str) -> Result<&mut Self, Error> {
-        if path.ends_with(".csv") {
-            let data = SourcesType::Csv(path);
-            self.record_batches = Some(data.read_data().await?);
-            Ok(self)
-        } else {
-            Err(Error::UnsupportedFormat(path.to_string()))
-        }
-    }
-
-    pub async fn write_delta(&mut self, bucket_name: &str, tb_name: &str) -> Result<(), Error> {
-        let sink = sinks::Delta::new(bucket_name);
-        self.enginee.delta_table_mapping(
-            &format!("{}/{}", bucket_name, tb_name),
-            &format!("delta_{}", tb_name),
-        )?;
-
-        let data_batches = match self.record_batches.as_ref() {
-            Some(batches) => batches,
-            None => {
-                return Err(Error::Delta(
-                    "Record batches are not initialized".to_string(),
-                ))
-            }
-        };
+        self.read_csv_with_options(path, &CsvOptions::default())
+            .await
+    }
 
-        sink.write(data_batches, tb_name).await?;
+    /// Reads `.csv` and `.tsv` files, optionally gzip or zstd compressed.
+    pub async fn read_csv_with_options(
+        &mut self,
+        path: &str,
+        options: &CsvOptions,
+    ) -> Result<&mut Self, Error> {
+        let data = SourcesType::Csv(path, options);
+        let (batches, rejected_batches) = data.read_with_rejects(self.enginee.storage()).await?;
+        self.set_batches(batches);
+        self.rejected_batches = rejected_batches;
+        Ok(self)
+    }
+
+    /// Rows skipped by the last CSV read that was configured with [`CsvOptions::rejects_table`].
+    pub fn rejected_rows(&self) -> Option<&Vec<RecordBatch>> {
+        self.rejected_batches.as_ref()
+    }
+
+    /// Reads newline delimited JSON or a JSON array, letting DuckDB infer the schema.
+    pub async fn read_json(&mut self, path: &str) -> Result<&mut Self, Error> {
+        self.read_json_with_options(path, &JsonOptions::default())
+            .await
+    }
+
+    pub async fn read_json_with_options(
+        &mut self,
+        path: &str,
+        options: &JsonOptions,
+    ) -> Result<&mut Self, Error> {
+        let data = SourcesType::Json(path, options);
+        let batches = data.read_with_storage(self.enginee.storage()).await?;
+        self.set_batches(batches);
+        Ok(self)
+    }
+
+    /// Reads a single parquet file, a glob pattern or a (hive partitioned) directory.
+    pub async fn read_parquet(&mut self, path: &str) -> Result<&mut Self, Error> {
+        let data = SourcesType::Parquet(path);
+        let batches = data.read_with_storage(self.enginee.storage()).await?;
+        self.set_batches(batches);
+        Ok(self)
+    }
+
+    /// Reads the latest snapshot of a delta table.
+    pub async fn read_delta(&mut self, uri: &str) -> Result<&mut Self, Error> {
+        self.read_delta_with_options(uri, &DeltaReadOptions::default())
+            .await
+    }
+
+    /// Reads a historical snapshot of a delta table, see [`DeltaReadOptions`].
+    pub async fn read_delta_with_options(
+        &mut self,
+        uri: &str,
+        options: &DeltaReadOptions,
+    ) -> Result<&mut Self, Error> {
+        let data = SourcesType::Delta(uri, options);
+        let batches = data.read_with_storage(self.enginee.storage()).await?;
+        self.set_batches(batches);
+        Ok(self)
+    }
+
+    /// Registers the active dataset as a temporary DuckDB table, so that
+    /// `execute_sql` can query them as `name` without writing them to storage first.
+    /// The table is a copy, later changes to the dataset are not seen by it.
+    pub async fn register_as(&mut self, name: &str) -> Result<&mut Self, Error> {
+        let data_batches = self.batches()?;
+
+        self.enginee.register_batches(name, data_batches).await?;
+        Ok(self)
+    }
+
+    pub async fn write_delta(
+        &mut self,
+        bucket_name: &str,
+        tb_name: &str,
+        mode: WriteMode,
+    ) -> Result<(), Error> {
+        self.write_delta_with_options(bucket_name, tb_name, &WriteOptions::new(mode))
+            .await
+    }
+
+    /// Writes to a delta table with partition columns, see [`WriteOptions`].
+    pub async fn write_delta_with_options(
+        &mut self,
+        bucket_name: &str,
+        tb_name: &str,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        let dataset = self.active_dataset.clone();
+        self.write_dataset(&dataset, bucket_name, tb_name, options)
+            .await
+    }
+
+    /// Writes the dataset `name` to a delta table, the active dataset stays selected.
+    pub async fn write_dataset(
+        &mut self,
+        name: &str,
+        bucket_name: &str,
+        tb_name: &str,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        let data_batches = self.dataset_batches(name)?;
+
+        self.map_delta_table(bucket_name, tb_name, options.get_table_alias())
+            .await?;
+        sink.write(data_batches, tb_name, options).await?;
         Ok(())
     }
 
+    /// Maps a written table to `table_alias`, or to `delta_<tb_name>` when there is none.
+    async fn map_delta_table(
+        &self,
+        bucket_name: &str,
+        tb_name: &str,
+        table_alias: Option<&str>,
+    ) -> Result<(), Error> {
+        let table_alias = match table_alias {
+            Some(table_alias) => table_alias.to_string(),
+            None => format!("delta_{}", tb_name),
+        };
+        self.enginee
+            .delta_table_mapping(&format!("{}/{}", bucket_name, tb_name), &table_alias)
+            .await
+    }
+
+    /// Makes an existing delta table queryable as `table_alias`, replacing an earlier
+    /// mapping of that name. A name that differs from a mapped one only by case fails with
+    /// [`Error::TableExists`].
+    pub async fn register_delta_table(
+        &mut self,
+        delta_path: &str,
+        table_alias: &str,
+    ) -> Result<&mut Self, Error> {
+        self.enginee
+            .delta_table_mapping(delta_path, table_alias)
+            .await?;
+        Ok(self)
+    }
+
+    /// Delta tables queries can refer to by name.
+    pub async fn list_tables(&self) -> Result<Vec<TableMapping>, Error> {
+        self.enginee.list_tables().await
+    }
+
+    pub async fn describe_table(&self, name: &str) -> Result<TableDescription, Error> {
+        self.enginee.describe_table(name).await
+    }
+
+    pub async fn unregister_table(&mut self, name: &str) -> Result<&mut Self, Error> {
+        self.enginee.unregister_table(name).await?;
+        Ok(self)
+    }
+
+    pub async fn rename_mapping(&mut self, name: &str, new_name: &str) -> Result<&mut Self, Error> {
+        self.enginee.rename_mapping(name, new_name).await?;
+        Ok(self)
+    }
+
+    /// Appends the result of a query to a delta table batch by batch, without collecting it
+    /// as record batches first. The table is created when it does not exist, see
+    /// [`StreamOptions`]. Other queries of the pipeline wait until the stream is written.
+    pub async fn stream_sql_to_delta(
+        &mut self,
+        query: &str,
+        bucket_name: &str,
+        tb_name: &str,
+        options: &StreamOptions,
+    ) -> Result<StreamMetrics, Error> {
+        // Mapped first, the DuckDB engine is busy with the query while it streams
+        self.map_delta_table(bucket_name, tb_name, options.get_table_alias())
+            .await?;
+        let batches = self
+            .enginee
+            .sql_stream(query, options.get_buffered_batches())
+            .await?;
+        self.write_stream(batches, bucket_name, tb_name, options)
+            .await
+    }
+
+    /// Appends a CSV, JSON, parquet or delta source to a delta table batch by batch, e.g.
+    /// `SourcesType::Csv(path, &CsvOptions::default())`, without collecting it as record
+    /// batches first.
+    pub async fn stream_source_to_delta(
+        &mut self,
+        source: SourcesType<'_>,
+        bucket_name: &str,
+        tb_name: &str,
+        options: &StreamOptions,
+    ) -> Result<StreamMetrics, Error> {
+        self.map_delta_table(bucket_name, tb_name, options.get_table_alias())
+            .await?;
+        let batches = source
+            .stream(options.get_buffered_batches(), self.enginee.storage())
+            .await?;
+        self.write_stream(batches, bucket_name, tb_name, options)
+            .await
+    }
+
+    async fn write_stream(
+        &self,
+        batches: engines::BatchStream,
+        bucket_name: &str,
+        tb_name: &str,
+        options: &StreamOptions,
+    ) -> Result<StreamMetrics, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        sink.write_stream(tb_name, batches, options).await
+    }
+
+    /// Upserts into a delta table: rows matching on `key_column` get `target_column`
+    /// updated, the other rows are inserted.
     pub async fn merge_update(
         &mut self,
         bucket_name: &str,
         table_path: &str,
         key_column: &str,
         target_column: &[&str],
-    ) -> Result<(), Error> {
-        let sink = sinks::Delta::new(bucket_name);
+    ) -> Result<MergeMetrics, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
         let full_path = format!("{}/{}", bucket_name, table_path);
 
-        let data_batches = match self.record_batches.as_ref() {
-            Some(batches) => batches,
-            None => {
-                return Err(Error::Delta(
-                    "Record batches are not initialized".to_string(),
-                ))
-            }
-        };
+        let data_batches = self.batches()?;
 
-        sink.merge_update(&full_path, data_batches, key_column, target_column)
+        let metrics = sink
+            .merge_update(&full_path, data_batches, key_column, target_column)
             .await?;
-        Ok(())
+        Ok(metrics)
+    }
+
+    /// Merges the active dataset into a delta table, see [`MergeSpec`].
+    pub async fn merge(
+        &mut self,
+        bucket_name: &str,
+        table_path: &str,
+        spec: &MergeSpec,
+    ) -> Result<MergeMetrics, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        let full_path = format!("{}/{}", bucket_name, table_path);
+
+        let data_batches = self.batches()?;
+
+        let metrics = sink.merge(&full_path, data_batches, spec).await?;
+        Ok(metrics)
+    }
+
+    /// Keeps the history of a dimension table as slowly changing dimension type 2: changed
+    /// rows are closed with `valid_to` / `is_current = false` and their new version inserted
+    /// with a fresh `valid_from`. The table is created on the first call.
+    pub async fn merge_scd2(
+        &mut self,
+        bucket_name: &str,
+        table_path: &str,
+        keys: &[&str],
+        tracked_columns: &[&str],
+    ) -> Result<MergeMetrics, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        let full_path = format!("{}/{}", bucket_name, table_path);
+
+        let data_batches = self.batches()?;
+
+        let metrics = sink
+            .merge_scd2(&full_path, data_batches, keys, tracked_columns)
+            .await?;
+        Ok(metrics)
+    }
+
+    /// Deletes the rows of a delta table matching a SQL predicate, e.g. `"id" = 42`,
+    /// and returns the number of deleted rows.
+    pub async fn delete(
+        &mut self,
+        bucket_name: &str,
+        table_path: &str,
+        predicate: &str,
+    ) -> Result<usize, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        let full_path = format!("{}/{}", bucket_name, table_path);
+        sink.delete(&full_path, predicate).await
+    }
+
+    /// Sets `(column, SQL expression)` assignments on the rows of a delta table matching
+    /// a SQL predicate and returns the number of updated rows.
+    pub async fn update(
+        &mut self,
+        bucket_name: &str,
+        table_path: &str,
+        predicate: &str,
+        assignments: &[(&str, &str)],
+    ) -> Result<usize, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        let full_path = format!("{}/{}", bucket_name, table_path);
+        sink.update(&full_path, predicate, assignments).await
+    }
+
+    /// Compacts small files of a delta table, or Z-orders them, see [`OptimizeOptions`].
+    pub async fn optimize(
+        &mut self,
+        bucket_name: &str,
+        tb_name: &str,
+        options: &OptimizeOptions,
+    ) -> Result<OptimizeMetrics, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        sink.optimize(tb_name, options).await
+    }
+
+    /// Removes files no longer referenced by a delta table, see [`VacuumOptions`].
+    pub async fn vacuum(
+        &mut self,
+        bucket_name: &str,
+        tb_name: &str,
+        options: &VacuumOptions,
+    ) -> Result<VacuumMetrics, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        sink.vacuum(tb_name, options).await
+    }
+
+    /// Writes a checkpoint of the latest version of a delta table and returns that version.
+    pub async fn checkpoint(&mut self, bucket_name: &str, tb_name: &str) -> Result<i64, Error> {
+        let sink = sinks::Delta::new_with_storage(bucket_name, self.enginee.storage());
+        sink.checkpoint(tb_name).await
     }
 
     pub async fn execute_sql(&mut self, query: &str) -> Result<&mut Self, Error> {
-        let result = self.enginee.sql(query)?;
-        self.record_batches = Some(result);
+        let result = self.enginee.sql(query).await?;
+        self.set_batches(result);
+        Ok(self)
+    }
+
+    /// Runs a query with bind parameters, e.g. `WHERE "City" = $city` with
+    /// `SqlParams::named(&[("city", "Chicago".into())])`.
+    pub async fn execute_sql_with_params(
+        &mut self,
+        query: &str,
+        params: &SqlParams,
+    ) -> Result<&mut Self, Error> {
+        let result = self.enginee.sql_with_params(query, params).await?;
+        self.set_batches(result);
+        Ok(self)
+    }
+
+    /// Renders the `{{ name }}` variables of a query template before running it,
+    /// see [`engines::render_template`].
+    pub async fn execute_sql_template(
+        &mut self,
+        template: &str,
+        vars: &HashMap<&str, SqlValue>,
+    ) -> Result<&mut Self, Error> {
+        let query = engines::render_template(template, vars)?;
+        self.execute_sql(&query).await
+    }
+
+    /// Runs a `.sql` file statement by statement, the result of its last query becomes
+    /// the active dataset.
+    pub async fn execute_script(&mut self, path: &str) -> Result<&mut Self, Error> {
+        let script = tokio::fs::read_to_string(path).await?;
+        let result = self.enginee.script(&script).await?;
+        self.set_batches(result);
         Ok(self)
     }
 
     pub async fn show(&mut self) -> Result<(), Error> {
         let session = SessionContext::new();
-        let df = session.read_batches(self.record_batches.clone().unwrap())?;
+        let df = session.read_batches(self.batches()?.clone())?;
         df.show().await?;
         Ok(())
     }
@@ -0,0 +1,66 @@
+use chrono::Duration;
+use deltalake::checkpoints::create_checkpoint;
+use deltalake::operations::optimize::OptimizeType;
+use deltalake::DeltaOps;
+
+use crate::error::Error;
+use crate::pipeline::storage::StorageConfig;
+
+use super::delta_sink::open_delta_table;
+use super::maintenance::{OptimizeMetrics, OptimizeOptions, VacuumMetrics, VacuumOptions};
+
+pub(crate) async fn optimize(
+    table_path: &str,
+    options: &OptimizeOptions,
+    storage: &StorageConfig,
+) -> Result<OptimizeMetrics, Error> {
+    let table = open_delta_table(table_path, storage).await?;
+
+    let z_order_columns = options.get_z_order_columns();
+    let optimize_type = if z_order_columns.is_empty() {
+        OptimizeType::Compact
+    } else {
+        OptimizeType::ZOrder(z_order_columns.to_vec())
+    };
+
+    let mut builder = DeltaOps(table).optimize().with_type(optimize_type);
+    if let Some(target_size) = options.get_target_size() {
+        builder = builder.with_target_size(target_size);
+    }
+
+    let (_, metrics) = builder.await?;
+    Ok(metrics)
+}
+
+pub(crate) async fn vacuum(
+    table_path: &str,
+    options: &VacuumOptions,
+    storage: &StorageConfig,
+) -> Result<VacuumMetrics, Error> {
+    let table = open_delta_table(table_path, storage).await?;
+
+    let mut builder = DeltaOps(table)
+        .vacuum()
+        .with_dry_run(options.get_dry_run())
+        .with_enforce_retention_duration(options.get_enforce_retention_duration());
+    if let Some(hours) = options.get_retention_hours() {
+        let retention_period = i64::try_from(hours)
+            .ok()
+            .and_then(Duration::try_hours)
+            .ok_or_else(|| {
+                Error::InvalidConfig(format!("A retention of {} hours is too long", hours))
+            })?;
+        builder = builder.with_retention_period(retention_period);
+    }
+
+    let (_, metrics) = builder.await?;
+    Ok(metrics)
+}
+
+pub(crate) async fn checkpoint(table_path: &str, storage: &StorageConfig) -> Result<i64, Error> {
+    let table = open_delta_table(table_path, storage).await?;
+    create_checkpoint(&table)
+        .await
+        .map_err(|e| Error::Delta(format!("Failed to create checkpoint: {}", e)))?;
+    Ok(table.version())
+}
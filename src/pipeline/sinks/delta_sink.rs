@@ -1,122 +1,473 @@
-use aws_config::BehaviorVersion;
-use deltalake::aws::constants::{
-    AWS_ALLOW_HTTP, AWS_ENDPOINT_URL, AWS_FORCE_CREDENTIAL_LOAD, AWS_S3_ALLOW_UNSAFE_RENAME,
-};
+use chrono::{DateTime, Utc};
 use deltalake::datafusion::prelude::{col, SessionContext};
-use deltalake::{
-    open_table, open_table_with_storage_options, DeltaOps, DeltaTable, DeltaTableError,
-};
+use deltalake::delta_datafusion::DataFusionMixins;
+use deltalake::kernel::StructField;
+use deltalake::operations::write::SchemaMode as DeltaSchemaMode;
+use deltalake::protocol::SaveMode;
+use deltalake::{open_table_with_storage_options, DeltaOps, DeltaTable};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use deltalake::arrow::array::RecordBatch;
 
 use crate::error::Error;
+use crate::pipeline::storage::StorageConfig;
+use crate::pipeline::uri::normalize_uri;
 
-async fn is_local_storage(uri: &str) -> Result<bool, DeltaTableError> {
-    if uri.starts_with("s3://") {
-        Ok(false)
-    } else if uri.starts_with("file://") {
-        Ok(true)
-    } else {
-        Err(DeltaTableError::Generic(
-            "storage type should start with either s3:// or file://".to_string(),
-        )
-        .into())
+use super::merge::{MergeClause, MergeMetrics, MergeSpec};
+use super::schema::{enforce_schema, SchemaDiff};
+use super::{SchemaMode, WriteMode, WriteOptions};
+
+/// Storage options of the table at a normalized `uri`, registering the handlers its
+/// scheme needs. Local and `memory://` tables need none.
+async fn storage_options(uri: &str, storage: &StorageConfig) -> HashMap<String, String> {
+    match uri.split_once("://").map(|(scheme, _)| scheme) {
+        Some("s3" | "s3a") => {
+            deltalake::aws::register_handlers(None);
+            storage.for_uri(uri).resolve().await.to_storage_options()
+        }
+        Some("az" | "abfs" | "abfss") => {
+            deltalake::azure::register_handlers(None);
+            storage.for_uri(uri).to_azure_storage_options()
+        }
+        Some("gs") => {
+            deltalake::gcp::register_handlers(None);
+            storage.for_uri(uri).to_gcs_storage_options()
+        }
+        _ => HashMap::new(),
     }
 }
 
-pub(crate) async fn write(delta_path: &str, data: &Vec<RecordBatch>) -> Result<DeltaTable, Error> {
-    // Check if the delta path is local or on AWS
-    let is_local_storage = is_local_storage(delta_path).await?;
-
-    match is_local_storage {
-        true => {
-            // Write to local storage
-            let table = DeltaOps::try_from_uri(delta_path)
-                .await?
-                .write(data.clone())
-                .await?;
-            Ok(table)
+/// Opens the operations of the table at `delta_path`, the table does not have to exist yet.
+pub(crate) async fn delta_ops(
+    delta_path: &str,
+    storage: &StorageConfig,
+) -> Result<DeltaOps, Error> {
+    let uri = normalize_uri(delta_path)?;
+    let storage_options = storage_options(&uri, storage).await;
+    Ok(DeltaOps::try_from_uri_with_storage_options(&uri, storage_options).await?)
+}
+
+pub(crate) async fn write(
+    delta_path: &str,
+    data: &[RecordBatch],
+    options: &WriteOptions,
+    storage: &StorageConfig,
+) -> Result<DeltaTable, Error> {
+    let ops = delta_ops(delta_path, storage).await?;
+
+    // The write builder does not honor SaveMode::Ignore, an existing table is left as is here
+    if options.get_mode() == &WriteMode::Ignore && ops.0.snapshot().is_ok() {
+        return Ok(ops.0);
+    }
+
+    // Partition columns can only be declared when the table is created
+    let partition_by = options.get_partition_by();
+    if let Ok(metadata) = ops.0.metadata() {
+        if !partition_by.is_empty() && metadata.partition_columns != partition_by {
+            return Err(Error::PartitionMismatch(format!(
+                "table {} is partitioned by {:?}, the write is partitioned by {:?}",
+                delta_path, metadata.partition_columns, partition_by
+            )));
         }
-        false => {
-            // Register AWS handlers and write to AWS storage
-            deltalake::aws::register_handlers(None);
-            let table = DeltaOps::try_from_uri_with_storage_options(delta_path, aws_config().await)
-                .await?
-                .write(data.clone())
-                .await?;
-            Ok(table)
+    }
+
+    let schema_mode = options.get_schema_mode();
+    if schema_mode == SchemaMode::OverwriteSchema && options.get_mode() != &WriteMode::Overwrite {
+        return Err(Error::SchemaMismatch(
+            "the schema can only be overwritten together with WriteMode::Overwrite".to_string(),
+        ));
+    }
+
+    // Only writes that add rows to an existing table are checked against its schema
+    let data = match (ops.0.snapshot(), options.get_mode()) {
+        (Ok(snapshot), WriteMode::Append | WriteMode::Overwrite | WriteMode::ReplaceWhere(_)) => {
+            enforce_schema(&*snapshot.input_schema()?, data, schema_mode, None)?
+        }
+        _ => data.to_vec(),
+    };
+
+    let mut builder = match options.get_mode() {
+        WriteMode::Append => ops.write(data).with_save_mode(SaveMode::Append),
+        WriteMode::Overwrite => ops.write(data).with_save_mode(SaveMode::Overwrite),
+        WriteMode::ErrorIfExists => ops.write(data).with_save_mode(SaveMode::ErrorIfExists),
+        WriteMode::Ignore => ops.write(data).with_save_mode(SaveMode::Ignore),
+        WriteMode::ReplaceWhere(predicate) => ops
+            .write(data)
+            .with_save_mode(SaveMode::Overwrite)
+            .with_replace_where(predicate.clone()),
+    };
+
+    if !partition_by.is_empty() {
+        builder = builder.with_partition_columns(partition_by.to_vec());
+    }
+
+    match schema_mode {
+        SchemaMode::Strict => (),
+        SchemaMode::Merge => builder = builder.with_schema_mode(DeltaSchemaMode::Merge),
+        SchemaMode::OverwriteSchema => {
+            builder = builder.with_schema_mode(DeltaSchemaMode::Overwrite)
         }
     }
+
+    let table = builder.await?;
+    Ok(table)
 }
 
-pub(crate) async fn merge_update(
+pub(crate) async fn merge(
     table_path: &str,
-    data_batches: &Vec<RecordBatch>,
-    key_column: &str,
-    target_column: &[&str],
-) -> Result<(), Error> {
+    data_batches: &[RecordBatch],
+    spec: &MergeSpec,
+    storage: &StorageConfig,
+) -> Result<MergeMetrics, Error> {
+    let schema_mode = spec.get_schema_mode();
+    if schema_mode == SchemaMode::OverwriteSchema {
+        return Err(Error::SchemaMismatch(
+            "the schema of a table cannot be overwritten by a merge".to_string(),
+        ));
+    }
+
+    let mut table = open_delta_table(table_path, storage).await?;
+    let data_columns: Vec<String> = match data_batches.first() {
+        Some(batch) => batch
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().to_string())
+            .collect(),
+        None => vec![],
+    };
+    let written_columns = spec.written_columns(&data_columns);
+    let table_schema = table.snapshot()?.input_schema()?;
+    let data_batches = enforce_schema(
+        &table_schema,
+        data_batches,
+        schema_mode,
+        Some(&written_columns),
+    )?;
+
+    // The merge cannot evolve the schema itself, written columns the table lacks are
+    // added as nullable columns first
+    if let (SchemaMode::Merge, Some(batch)) = (schema_mode, data_batches.first()) {
+        let data_schema = batch.schema();
+        let mut diff = SchemaDiff::between(&table_schema, &data_schema);
+        diff.added.retain(|column| written_columns.contains(column));
+
+        let fields = diff
+            .added
+            .iter()
+            .map(|column| {
+                let field = data_schema.field_with_name(column)?.clone();
+                StructField::try_from(&field.with_nullable(true))
+            })
+            .collect::<Result<Vec<StructField>, _>>()?;
+        if !fields.is_empty() {
+            table = DeltaOps(table).add_columns().with_fields(fields).await?;
+        }
+    }
+
     let ctx = SessionContext::new();
-    let source = ctx.read_batches(data_batches.clone())?;
-    let table = open_delta_table(table_path).await?;
-    DeltaOps(table)
-        .merge(
-            source,
-            col(format!("target.\"{}\"", key_column)).eq(col(format!("source.\"{}\"", key_column))),
-        )
+    let source = ctx.read_batches(data_batches)?;
+    let source_columns: Vec<String> = source
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| format!("\"{}\"", field.name()))
+        .collect();
+
+    let mut builder = DeltaOps(table)
+        .merge(source, spec.join_predicate()?)
         .with_source_alias("source")
-        .with_target_alias("target")
-        .when_matched_update(|mut update| {
-            for target in target_column {
-                let cleaned_target = format!("\"{}\"", target);
-                update = update.update(&cleaned_target, col(format!("source.{}", cleaned_target)));
+        .with_target_alias("target");
+
+    for clause in spec.clauses() {
+        builder = match clause {
+            MergeClause::MatchedUpdate {
+                predicate,
+                assignments,
+            } => builder.when_matched_update(|mut update| {
+                if let Some(predicate) = predicate {
+                    update = update.predicate(predicate.as_str());
+                }
+                for (column, value) in assignments {
+                    update = update.update(format!("\"{}\"", column), value.as_str());
+                }
+                update
+            })?,
+            MergeClause::MatchedUpdateAll { predicate } => {
+                builder.when_matched_update(|mut update| {
+                    if let Some(predicate) = predicate {
+                        update = update.predicate(predicate.as_str());
+                    }
+                    for column in &source_columns {
+                        update = update.update(column, col(format!("source.{}", column)));
+                    }
+                    update
+                })?
+            }
+            MergeClause::MatchedDelete { predicate } => {
+                builder.when_matched_delete(|mut delete| {
+                    if let Some(predicate) = predicate {
+                        delete = delete.predicate(predicate.as_str());
+                    }
+                    delete
+                })?
             }
-            update
-        })?
-        .await?;
+            MergeClause::NotMatchedInsert {
+                predicate,
+                assignments,
+            } => builder.when_not_matched_insert(|mut insert| {
+                if let Some(predicate) = predicate {
+                    insert = insert.predicate(predicate.as_str());
+                }
+                for (column, value) in assignments {
+                    insert = insert.set(format!("\"{}\"", column), value.as_str());
+                }
+                insert
+            })?,
+            MergeClause::NotMatchedInsertAll { predicate } => {
+                builder.when_not_matched_insert(|mut insert| {
+                    if let Some(predicate) = predicate {
+                        insert = insert.predicate(predicate.as_str());
+                    }
+                    for column in &source_columns {
+                        insert = insert.set(column, col(format!("source.{}", column)));
+                    }
+                    insert
+                })?
+            }
+            MergeClause::NotMatchedBySourceDelete { predicate } => builder
+                .when_not_matched_by_source_delete(|mut delete| {
+                    if let Some(predicate) = predicate {
+                        delete = delete.predicate(predicate.as_str());
+                    }
+                    delete
+                })?,
+        };
+    }
 
-    Ok(())
+    let (_, metrics) = builder.await?;
+    Ok(metrics)
 }
 
-/// strips file:/// prefix from the uri
-async fn strip_file_prefix(uri: &str) -> Result<&str, DeltaTableError> {
-    if let Some(path) = uri.strip_prefix("file://") {
-        Ok(path)
-    } else if let Some(path) = uri.strip_prefix("s3://") {
-        let _ = path;
-        Ok(uri)
-    } else {
-        return Err(DeltaTableError::Generic(
-            "strip_file_prefix called on a non-file uri".to_string(),
+/// Deletes the rows matching a SQL predicate and returns how many were deleted.
+pub(crate) async fn delete(
+    table_path: &str,
+    predicate: &str,
+    storage: &StorageConfig,
+) -> Result<usize, Error> {
+    let table = open_delta_table(table_path, storage).await?;
+    let (_, metrics) = DeltaOps(table).delete().with_predicate(predicate).await?;
+    Ok(metrics.num_deleted_rows)
+}
+
+/// Sets `(column, value)` assignments on the rows matching a SQL predicate and returns
+/// how many were updated.
+pub(crate) async fn update(
+    table_path: &str,
+    predicate: &str,
+    assignments: &[(&str, &str)],
+    storage: &StorageConfig,
+) -> Result<usize, Error> {
+    if assignments.is_empty() {
+        return Err(Error::Delta(
+            "update needs at least one assignment".to_string(),
         ));
     }
+
+    let table = open_delta_table(table_path, storage).await?;
+    let mut builder = DeltaOps(table).update().with_predicate(predicate);
+    for (column, value) in assignments {
+        builder = builder.with_update(format!("\"{}\"", column), *value);
+    }
+
+    let (_, metrics) = builder.await?;
+    Ok(metrics.num_updated_rows)
 }
 
-async fn open_delta_table(uri: &str) -> Result<DeltaTable, DeltaTableError> {
-    let is_local_storage = is_local_storage(uri).await?;
-    if is_local_storage {
-        open_table(uri).await
-    } else {
-        deltalake::aws::register_handlers(None);
-        let uri = strip_file_prefix(uri).await?;
-        open_table_with_storage_options(uri, aws_config().await).await
+/// Arrow type of the `valid_from` / `valid_to` columns of SCD type 2 tables
+const SCD2_TIMESTAMP_TYPE: &str = "Timestamp(Microsecond, Some(\"UTC\"))";
+
+/// Merges `data_batches` into a slowly changing dimension (type 2) table in one commit.
+///
+/// Current rows (`is_current = true`) whose `tracked_columns` differ from the source get
+/// `valid_to` set and `is_current = false`, and the source row is inserted as the new current
+/// version. Unchanged rows are left alone. Keys without a current row, either new ones or
+/// late-arriving ones whose history was already closed, are inserted as current rows.
+pub(crate) async fn merge_scd2(
+    table_path: &str,
+    data_batches: &[RecordBatch],
+    keys: &[&str],
+    tracked_columns: &[&str],
+    storage: &StorageConfig,
+) -> Result<MergeMetrics, Error> {
+    if keys.is_empty() || tracked_columns.is_empty() {
+        return Err(Error::Delta(
+            "merge_scd2 needs at least one key and one tracked column".to_string(),
+        ));
     }
+
+    let ctx = SessionContext::new();
+    let updates = ctx.read_batches(data_batches.to_vec())?;
+    let columns: Vec<String> = updates
+        .schema()
+        .fields()
+        .iter()
+        .map(|field| field.name().to_string())
+        .collect();
+    ctx.register_table("scd_updates", updates.into_view())?;
+
+    let load_time = format!(
+        "arrow_cast('{}', '{}')",
+        Utc::now().format("%Y-%m-%dT%H:%M:%S%.6fZ"),
+        SCD2_TIMESTAMP_TYPE
+    );
+    let no_time = format!("arrow_cast(NULL, '{}')", SCD2_TIMESTAMP_TYPE);
+    let update_columns = columns
+        .iter()
+        .map(|column| format!("u.\"{}\"", column))
+        .collect::<Vec<String>>()
+        .join(", ");
+
+    let table = match delta_ops(table_path, storage).await? {
+        ops if ops.0.snapshot().is_ok() => ops.0,
+        _ => {
+            // The first load creates the table with every row as the current version
+            let initial_sql = format!(
+                "SELECT {}, {} AS \"valid_from\", {} AS \"valid_to\", true AS \"is_current\" FROM scd_updates u",
+                update_columns, load_time, no_time
+            );
+            let initial_batches = ctx.sql(&initial_sql).await?.collect().await?;
+            let metrics = MergeMetrics {
+                num_source_rows: initial_batches.iter().map(|b| b.num_rows()).sum(),
+                num_target_rows_inserted: initial_batches.iter().map(|b| b.num_rows()).sum(),
+                ..Default::default()
+            };
+            write(
+                table_path,
+                &initial_batches,
+                &WriteOptions::new(WriteMode::ErrorIfExists),
+                storage,
+            )
+            .await?;
+            return Ok(metrics);
+        }
+    };
+    ctx.register_table("scd_target", Arc::new(table))?;
+
+    // Every source row is staged with its key to close out the current version, changed rows
+    // are staged a second time with a NULL key so that they never match and get inserted.
+    let key_columns: Vec<String> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| format!("u.\"{}\" AS \"__scd_key_{}\"", key, i))
+        .collect();
+    let null_key_columns: Vec<String> = (0..keys.len())
+        .map(|i| format!("NULL AS \"__scd_key_{}\"", i))
+        .collect();
+    let join_keys: Vec<String> = keys
+        .iter()
+        .map(|key| format!("t.\"{}\" = u.\"{}\"", key, key))
+        .collect();
+    // Parenthesized, the parser otherwise reads the right side of IS DISTINCT FROM up to the next OR
+    let changed: Vec<String> = tracked_columns
+        .iter()
+        .map(|column| format!("(t.\"{}\" IS DISTINCT FROM u.\"{}\")", column, column))
+        .collect();
+
+    let staged_sql = format!(
+        "SELECT {columns}, {keys}, {load_time} AS \"valid_from\" FROM scd_updates u \
+         UNION ALL \
+         SELECT {columns}, {null_keys}, {load_time} AS \"valid_from\" FROM scd_updates u \
+         JOIN scd_target t ON {join} AND t.\"is_current\" = true WHERE {changed}",
+        columns = update_columns,
+        keys = key_columns.join(", "),
+        null_keys = null_key_columns.join(", "),
+        load_time = load_time,
+        join = join_keys.join(" AND "),
+        changed = changed.join(" OR "),
+    );
+    let staged_batches = ctx.sql(&staged_sql).await?.collect().await?;
+
+    let on: Vec<String> = keys
+        .iter()
+        .enumerate()
+        .map(|(i, key)| format!("target.\"{}\" = source.\"__scd_key_{}\"", key, i))
+        .chain(std::iter::once("target.\"is_current\" = true".to_string()))
+        .collect();
+    let changed: Vec<String> = tracked_columns
+        .iter()
+        .map(|column| {
+            format!(
+                "(target.\"{}\" IS DISTINCT FROM source.\"{}\")",
+                column, column
+            )
+        })
+        .collect();
+
+    let mut insert_values: Vec<(String, String)> = columns
+        .iter()
+        .map(|column| (column.clone(), format!("source.\"{}\"", column)))
+        .collect();
+    insert_values.push((
+        "valid_from".to_string(),
+        "source.\"valid_from\"".to_string(),
+    ));
+    insert_values.push(("valid_to".to_string(), no_time));
+    insert_values.push(("is_current".to_string(), "true".to_string()));
+    let insert_values: Vec<(&str, &str)> = insert_values
+        .iter()
+        .map(|(column, value)| (column.as_str(), value.as_str()))
+        .collect();
+
+    let spec = MergeSpec::new(&[])
+        .on(&on.join(" AND "))
+        .when_matched_update(
+            Some(&changed.join(" OR ")),
+            &[
+                ("is_current", "false"),
+                ("valid_to", "source.\"valid_from\""),
+            ],
+        )
+        .when_not_matched_insert(None, &insert_values);
+
+    merge(table_path, &staged_batches, &spec, storage).await
 }
 
-async fn aws_config() -> HashMap<String, String> {
-    let mut storage_options = HashMap::new();
+pub(crate) async fn open_delta_table(
+    uri: &str,
+    storage: &StorageConfig,
+) -> Result<DeltaTable, Error> {
+    let uri = normalize_uri(uri)?;
+    let storage_options = storage_options(&uri, storage).await;
+    Ok(open_table_with_storage_options(&uri, storage_options).await?)
+}
 
-    storage_options.insert(AWS_FORCE_CREDENTIAL_LOAD.to_string(), "true".to_string());
-    storage_options.insert(AWS_S3_ALLOW_UNSAFE_RENAME.to_string(), "true".to_string());
-    let config = aws_config::load_defaults(BehaviorVersion::v2024_03_28()).await;
+/// Opens a delta table at its latest version, or time travels to the given version or
+/// RFC 3339 timestamp.
+pub(crate) async fn open_delta_table_as_of(
+    uri: &str,
+    version: Option<i64>,
+    timestamp: Option<&str>,
+    storage: &StorageConfig,
+) -> Result<DeltaTable, Error> {
+    let mut table = open_delta_table(uri, storage).await?;
 
-    match config.endpoint_url() {
-        Some(endpoint) => {
-            storage_options.insert(AWS_ENDPOINT_URL.to_string(), endpoint.to_string());
-            storage_options.insert(AWS_ALLOW_HTTP.to_string(), "true".to_string());
+    match (version, timestamp) {
+        (Some(_), Some(_)) => {
+            return Err(Error::Delta(
+                "as_of_version and as_of_timestamp cannot be used together".to_string(),
+            ))
+        }
+        (Some(version), None) => table.load_version(version).await?,
+        (None, Some(timestamp)) => {
+            let datetime = DateTime::parse_from_rfc3339(timestamp)
+                .map_err(|e| Error::Delta(format!("Invalid timestamp {}: {}", timestamp, e)))?
+                .with_timezone(&Utc);
+            table.load_with_datetime(datetime).await?
         }
-        None => (),
+        (None, None) => (),
     }
 
-    storage_options
+    Ok(table)
 }
@@ -0,0 +1,122 @@
+use std::sync::Arc;
+
+use deltalake::arrow::datatypes::Schema as ArrowSchema;
+use deltalake::delta_datafusion::DataFusionMixins;
+use deltalake::kernel::{Action, StructType};
+use deltalake::operations::transaction::CommitBuilder;
+use deltalake::protocol::{DeltaOperation, SaveMode};
+use deltalake::writer::{DeltaWriter, RecordBatchWriter};
+use deltalake::DeltaOps;
+
+use crate::error::Error;
+use crate::pipeline::engines::BatchStream;
+use crate::pipeline::storage::StorageConfig;
+
+use super::delta_sink::delta_ops;
+use super::schema::enforce_schema;
+use super::stream::{StreamMetrics, StreamOptions};
+use super::SchemaMode;
+
+/// Appends a stream of batches to a delta table without holding more than the batches
+/// waiting in the stream and the parquet buffer of the writer in memory.
+///
+/// A file is written whenever the buffer reaches the target file size, all files are
+/// committed together once the stream ends. A missing table is created with the schema
+/// of the first batch in that same commit. When the stream fails nothing is committed,
+/// files written until then are left for VACUUM.
+pub(crate) async fn write_stream(
+    table_path: &str,
+    mut batches: BatchStream,
+    options: &StreamOptions,
+    storage: &StorageConfig,
+) -> Result<StreamMetrics, Error> {
+    let mut metrics = StreamMetrics::default();
+    let first = match batches.recv().await {
+        Some(batch) => batch?,
+        None => return Ok(metrics),
+    };
+
+    let table = delta_ops(table_path, storage).await?.0;
+    let exists = table.snapshot().is_ok();
+
+    let (table_schema, partition_by) = if exists {
+        let metadata = table.metadata()?;
+        let partition_by = options.get_partition_by();
+        if !partition_by.is_empty() && metadata.partition_columns != partition_by {
+            return Err(Error::PartitionMismatch(format!(
+                "table {} is partitioned by {:?}, the write is partitioned by {:?}",
+                table_path, metadata.partition_columns, partition_by
+            )));
+        }
+        (
+            table.snapshot()?.input_schema()?,
+            metadata.partition_columns.clone(),
+        )
+    } else {
+        // Round trip through the delta schema, the files must hold the types of the table
+        let schema = StructType::try_from(first.schema().as_ref())?;
+        (
+            Arc::new(ArrowSchema::try_from(&schema)?),
+            options.get_partition_by().to_vec(),
+        )
+    };
+
+    let mut writer = if exists {
+        RecordBatchWriter::for_table(&table)?
+    } else {
+        RecordBatchWriter::try_new(
+            table.table_uri(),
+            table_schema.clone(),
+            Some(partition_by.clone()),
+            Some(table.log_store().config().options.0.clone()),
+        )?
+    };
+
+    let mut adds = vec![];
+    let mut next = Some(Ok(first));
+    while let Some(batch) = next {
+        for batch in enforce_schema(&table_schema, &[batch?], SchemaMode::Strict, None)? {
+            metrics.num_rows += batch.num_rows();
+            writer.write(batch).await?;
+        }
+        metrics.num_batches += 1;
+
+        if writer.buffer_len() >= options.get_target_file_size() {
+            adds.extend(writer.flush().await?);
+        }
+        next = batches.recv().await;
+    }
+    adds.extend(writer.flush().await?);
+    metrics.num_files = adds.len();
+    let actions: Vec<Action> = adds.into_iter().map(Action::Add).collect();
+
+    if !exists {
+        let schema = StructType::try_from(table_schema.as_ref())?;
+        let mut builder = DeltaOps(table)
+            .create()
+            .with_columns(schema.fields().cloned())
+            .with_actions(actions);
+        if !partition_by.is_empty() {
+            builder = builder.with_partition_columns(partition_by);
+        }
+        metrics.version = Some(builder.await?.version());
+        return Ok(metrics);
+    }
+
+    let operation = DeltaOperation::Write {
+        mode: SaveMode::Append,
+        partition_by: if partition_by.is_empty() {
+            None
+        } else {
+            Some(partition_by)
+        },
+        predicate: None,
+    };
+    let commit = CommitBuilder::default()
+        .with_actions(actions)
+        .build(Some(table.snapshot()?), table.log_store(), operation)
+        .await?;
+
+    metrics.version = Some(commit.version());
+    Ok(metrics)
+}
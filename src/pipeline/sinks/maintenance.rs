@@ -0,0 +1,135 @@
+use async_trait::async_trait;
+
+pub use deltalake::operations::optimize::Metrics as OptimizeMetrics;
+pub use deltalake::operations::vacuum::VacuumMetrics;
+
+use crate::error::Error;
+
+use super::{delta_maintenance, Delta};
+
+/// Options of an OPTIMIZE run. Files are bin-packed unless Z-order columns are set.
+#[derive(Clone, Debug, Default)]
+pub struct OptimizeOptions {
+    target_size: Option<i64>,
+    z_order_columns: Vec<String>,
+}
+
+impl OptimizeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size in bytes of the files written by the compaction, the table's
+    /// `delta.targetFileSize` (or 100MB) by default.
+    pub fn target_size(mut self, target_size: i64) -> Self {
+        self.target_size = Some(target_size);
+        self
+    }
+
+    /// Rewrites the files clustered by a Z-order curve over `columns`.
+    pub fn z_order(mut self, columns: &[&str]) -> Self {
+        self.z_order_columns = columns.iter().map(|column| column.to_string()).collect();
+        self
+    }
+
+    pub(crate) fn get_target_size(&self) -> Option<i64> {
+        self.target_size
+    }
+
+    pub(crate) fn get_z_order_columns(&self) -> &[String] {
+        &self.z_order_columns
+    }
+}
+
+/// Options of a VACUUM run.
+#[derive(Clone, Debug)]
+pub struct VacuumOptions {
+    retention_hours: Option<u64>,
+    dry_run: bool,
+    enforce_retention_duration: bool,
+}
+
+impl Default for VacuumOptions {
+    fn default() -> Self {
+        VacuumOptions {
+            retention_hours: None,
+            dry_run: false,
+            enforce_retention_duration: true,
+        }
+    }
+}
+
+impl VacuumOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Removes files that are no longer referenced since more than `hours`, the table's
+    /// `delta.deletedFileRetentionDuration` (or 7 days) by default.
+    pub fn retention_hours(mut self, hours: u64) -> Self {
+        self.retention_hours = Some(hours);
+        self
+    }
+
+    /// Only lists the files that would be removed.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Allows a retention shorter than the table's configured retention when set to false.
+    pub fn enforce_retention_duration(mut self, enforce: bool) -> Self {
+        self.enforce_retention_duration = enforce;
+        self
+    }
+
+    pub(crate) fn get_retention_hours(&self) -> Option<u64> {
+        self.retention_hours
+    }
+
+    pub(crate) fn get_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    pub(crate) fn get_enforce_retention_duration(&self) -> bool {
+        self.enforce_retention_duration
+    }
+}
+
+/// Table maintenance of the tables below a sink's path.
+#[async_trait]
+pub trait Maintenance {
+    async fn optimize(
+        &self,
+        tb_name: &str,
+        options: &OptimizeOptions,
+    ) -> Result<OptimizeMetrics, Error>;
+    async fn vacuum(&self, tb_name: &str, options: &VacuumOptions) -> Result<VacuumMetrics, Error>;
+    /// Writes a checkpoint of the latest version and returns that version.
+    async fn checkpoint(&self, tb_name: &str) -> Result<i64, Error>;
+}
+
+#[async_trait]
+impl Maintenance for Delta {
+    async fn optimize(
+        &self,
+        tb_name: &str,
+        options: &OptimizeOptions,
+    ) -> Result<OptimizeMetrics, Error> {
+        let full_path = format!("{}/{}", self.path, tb_name);
+        let metrics = delta_maintenance::optimize(&full_path, options, &self.storage).await?;
+        Ok(metrics)
+    }
+
+    async fn vacuum(&self, tb_name: &str, options: &VacuumOptions) -> Result<VacuumMetrics, Error> {
+        let full_path = format!("{}/{}", self.path, tb_name);
+        let metrics = delta_maintenance::vacuum(&full_path, options, &self.storage).await?;
+        Ok(metrics)
+    }
+
+    async fn checkpoint(&self, tb_name: &str) -> Result<i64, Error> {
+        let full_path = format!("{}/{}", self.path, tb_name);
+        let version = delta_maintenance::checkpoint(&full_path, &self.storage).await?;
+        Ok(version)
+    }
+}
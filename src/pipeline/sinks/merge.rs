@@ -0,0 +1,192 @@
+pub use deltalake::operations::merge::MergeMetrics;
+
+use crate::error::Error;
+
+use super::SchemaMode;
+
+/// A clause of a merge. Predicates and assigned values are SQL expressions over the
+/// `source` and `target` aliases, e.g. `source."updated_at" > target."updated_at"`.
+#[derive(Clone, Debug)]
+pub(crate) enum MergeClause {
+    MatchedUpdate {
+        predicate: Option<String>,
+        assignments: Vec<(String, String)>,
+    },
+    MatchedUpdateAll {
+        predicate: Option<String>,
+    },
+    MatchedDelete {
+        predicate: Option<String>,
+    },
+    NotMatchedInsert {
+        predicate: Option<String>,
+        assignments: Vec<(String, String)>,
+    },
+    NotMatchedInsertAll {
+        predicate: Option<String>,
+    },
+    NotMatchedBySourceDelete {
+        predicate: Option<String>,
+    },
+}
+
+/// Describes how source rows are merged into a delta table. Clauses of the same kind are
+/// evaluated in the order they were added, the first one whose predicate matches is applied.
+#[derive(Clone, Debug)]
+pub struct MergeSpec {
+    keys: Vec<String>,
+    predicate: Option<String>,
+    clauses: Vec<MergeClause>,
+    schema_mode: SchemaMode,
+}
+
+fn to_owned_predicate(predicate: Option<&str>) -> Option<String> {
+    predicate.map(|predicate| predicate.to_string())
+}
+
+fn to_owned_assignments(assignments: &[(&str, &str)]) -> Vec<(String, String)> {
+    assignments
+        .iter()
+        .map(|(column, value)| (column.to_string(), value.to_string()))
+        .collect()
+}
+
+impl MergeSpec {
+    /// Joins source and target rows on equality of all `keys`.
+    pub fn new(keys: &[&str]) -> Self {
+        MergeSpec {
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            predicate: None,
+            clauses: vec![],
+            schema_mode: SchemaMode::default(),
+        }
+    }
+
+    /// Updates every column of matched rows and inserts unmatched rows.
+    pub fn upsert(keys: &[&str]) -> Self {
+        Self::new(keys)
+            .when_matched_update_all(None)
+            .when_not_matched_insert_all(None)
+    }
+
+    /// Adds a predicate to the join condition, e.g. `target."date" >= '2024-12-01'`
+    /// to only scan recent partitions of the target.
+    pub fn on(mut self, predicate: &str) -> Self {
+        self.predicate = Some(predicate.to_string());
+        self
+    }
+
+    /// Sets `(column, value)` assignments on matched target rows.
+    pub fn when_matched_update(
+        mut self,
+        predicate: Option<&str>,
+        assignments: &[(&str, &str)],
+    ) -> Self {
+        self.clauses.push(MergeClause::MatchedUpdate {
+            predicate: to_owned_predicate(predicate),
+            assignments: to_owned_assignments(assignments),
+        });
+        self
+    }
+
+    /// Sets every column of matched target rows to the source value.
+    pub fn when_matched_update_all(mut self, predicate: Option<&str>) -> Self {
+        self.clauses.push(MergeClause::MatchedUpdateAll {
+            predicate: to_owned_predicate(predicate),
+        });
+        self
+    }
+
+    pub fn when_matched_delete(mut self, predicate: Option<&str>) -> Self {
+        self.clauses.push(MergeClause::MatchedDelete {
+            predicate: to_owned_predicate(predicate),
+        });
+        self
+    }
+
+    /// Inserts a row built from `(column, value)` assignments for unmatched source rows.
+    pub fn when_not_matched_insert(
+        mut self,
+        predicate: Option<&str>,
+        assignments: &[(&str, &str)],
+    ) -> Self {
+        self.clauses.push(MergeClause::NotMatchedInsert {
+            predicate: to_owned_predicate(predicate),
+            assignments: to_owned_assignments(assignments),
+        });
+        self
+    }
+
+    /// Inserts unmatched source rows as they are.
+    pub fn when_not_matched_insert_all(mut self, predicate: Option<&str>) -> Self {
+        self.clauses.push(MergeClause::NotMatchedInsertAll {
+            predicate: to_owned_predicate(predicate),
+        });
+        self
+    }
+
+    /// Deletes target rows that have no matching source row.
+    pub fn when_not_matched_by_source_delete(mut self, predicate: Option<&str>) -> Self {
+        self.clauses.push(MergeClause::NotMatchedBySourceDelete {
+            predicate: to_owned_predicate(predicate),
+        });
+        self
+    }
+
+    /// [`SchemaMode::Merge`] adds source columns missing from the table, source rows don't
+    /// need to have every column of the table in either mode.
+    pub fn schema_mode(mut self, schema_mode: SchemaMode) -> Self {
+        self.schema_mode = schema_mode;
+        self
+    }
+
+    /// The join condition built from the keys and the extra predicate.
+    pub(crate) fn join_predicate(&self) -> Result<String, Error> {
+        let mut conditions: Vec<String> = self
+            .keys
+            .iter()
+            .map(|key| format!("target.\"{}\" = source.\"{}\"", key, key))
+            .collect();
+
+        if let Some(predicate) = &self.predicate {
+            conditions.push(format!("({})", predicate));
+        }
+
+        if conditions.is_empty() {
+            return Err(Error::Delta(
+                "merge needs at least one key or a join predicate".to_string(),
+            ));
+        }
+
+        Ok(conditions.join(" AND "))
+    }
+
+    pub(crate) fn clauses(&self) -> &[MergeClause] {
+        &self.clauses
+    }
+
+    /// Columns of the target written by the clauses, given the columns of the source.
+    pub(crate) fn written_columns(&self, source_columns: &[String]) -> Vec<String> {
+        let mut written_columns: Vec<String> = vec![];
+
+        for clause in &self.clauses {
+            match clause {
+                MergeClause::MatchedUpdate { assignments, .. }
+                | MergeClause::NotMatchedInsert { assignments, .. } => {
+                    written_columns.extend(assignments.iter().map(|(column, _)| column.clone()))
+                }
+                MergeClause::MatchedUpdateAll { .. } | MergeClause::NotMatchedInsertAll { .. } => {
+                    written_columns.extend(source_columns.iter().cloned())
+                }
+                MergeClause::MatchedDelete { .. }
+                | MergeClause::NotMatchedBySourceDelete { .. } => {}
+            }
+        }
+
+        written_columns
+    }
+
+    pub(crate) fn get_schema_mode(&self) -> SchemaMode {
+        self.schema_mode
+    }
+}
@@ -1,49 +1,244 @@
 use crate::error::Error;
+use crate::pipeline::storage::StorageConfig;
 use async_trait::async_trait;
 use deltalake::arrow::array::RecordBatch;
 
-mod delta_sink;
+mod delta_maintenance;
+pub(crate) mod delta_sink;
+mod delta_stream;
+mod maintenance;
+mod merge;
+mod schema;
+mod stream;
+
+pub use maintenance::{
+    Maintenance, OptimizeMetrics, OptimizeOptions, VacuumMetrics, VacuumOptions,
+};
+pub use merge::{MergeMetrics, MergeSpec};
+pub use stream::{StreamMetrics, StreamOptions, StreamSink};
+
+/// How a write treats a delta table that already exists.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WriteMode {
+    /// Adds the rows to the table.
+    Append,
+    /// Replaces all rows of the table.
+    Overwrite,
+    /// Fails when the table already exists.
+    ErrorIfExists,
+    /// Skips the write when the table already exists.
+    Ignore,
+    /// Replaces only the rows matching a SQL predicate, e.g. `"date = '2024-12-01'"`.
+    /// All written rows have to match the predicate as well.
+    ReplaceWhere(String),
+}
+
+/// How the schema of written data is checked against an existing delta table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SchemaMode {
+    /// Rejects data with added, missing or type changed columns. Types that can be
+    /// widened safely, e.g. int32 to int64, are accepted.
+    #[default]
+    Strict,
+    /// Adds new columns to the table as nullable columns, missing columns are written as null.
+    Merge,
+    /// Replaces the schema of the table, only allowed with [`WriteMode::Overwrite`].
+    OverwriteSchema,
+}
+
+/// Options of a delta write.
+#[derive(Clone, Debug)]
+pub struct WriteOptions {
+    mode: WriteMode,
+    partition_by: Vec<String>,
+    schema_mode: SchemaMode,
+    table_alias: Option<String>,
+}
+
+impl WriteOptions {
+    pub fn new(mode: WriteMode) -> Self {
+        WriteOptions {
+            mode,
+            partition_by: vec![],
+            schema_mode: SchemaMode::default(),
+            table_alias: None,
+        }
+    }
+
+    /// Partition columns of the table. They are applied when the write creates the table,
+    /// writes to an existing table fail when its partition columns are different.
+    pub fn partition_by(mut self, columns: &[&str]) -> Self {
+        self.partition_by = columns.iter().map(|column| column.to_string()).collect();
+        self
+    }
+
+    pub fn schema_mode(mut self, schema_mode: SchemaMode) -> Self {
+        self.schema_mode = schema_mode;
+        self
+    }
+
+    /// Name the table is mapped to for queries, `delta_<tb_name>` by default.
+    pub fn table_alias(mut self, table_alias: &str) -> Self {
+        self.table_alias = Some(table_alias.to_string());
+        self
+    }
+
+    pub(crate) fn get_mode(&self) -> &WriteMode {
+        &self.mode
+    }
+
+    pub(crate) fn get_partition_by(&self) -> &[String] {
+        &self.partition_by
+    }
+
+    pub(crate) fn get_schema_mode(&self) -> SchemaMode {
+        self.schema_mode
+    }
+
+    pub(crate) fn get_table_alias(&self) -> Option<&str> {
+        self.table_alias.as_deref()
+    }
+}
+
+impl From<WriteMode> for WriteOptions {
+    fn from(mode: WriteMode) -> Self {
+        WriteOptions::new(mode)
+    }
+}
 
 #[async_trait]
 pub trait Sinks {
-    async fn write(&self, data: &Vec<RecordBatch>, folder_path: &str) -> Result<(), Error>;
+    async fn write(
+        &self,
+        data: &[RecordBatch],
+        folder_path: &str,
+        options: &WriteOptions,
+    ) -> Result<(), Error>;
+    async fn merge(
+        &self,
+        table_path: &str,
+        data_batches: &[RecordBatch],
+        spec: &MergeSpec,
+    ) -> Result<MergeMetrics, Error>;
     async fn merge_update(
         &self,
         table_path: &str,
-        data_batches: &Vec<RecordBatch>,
+        data_batches: &[RecordBatch],
         key_column: &str,
         target_column: &[&str],
-    ) -> Result<(), Error>;
+    ) -> Result<MergeMetrics, Error>;
+    async fn merge_scd2(
+        &self,
+        table_path: &str,
+        data_batches: &[RecordBatch],
+        keys: &[&str],
+        tracked_columns: &[&str],
+    ) -> Result<MergeMetrics, Error>;
+    async fn delete(&self, table_path: &str, predicate: &str) -> Result<usize, Error>;
+    async fn update(
+        &self,
+        table_path: &str,
+        predicate: &str,
+        assignments: &[(&str, &str)],
+    ) -> Result<usize, Error>;
 }
 
 pub struct Delta {
     path: String,
+    storage: StorageConfig,
 }
 
 impl Delta {
     pub fn new(path: &str) -> Self {
+        Self::new_with_storage(path, &StorageConfig::default())
+    }
+
+    /// A sink reaching its tables with the given storage settings, see [`StorageConfig`].
+    pub fn new_with_storage(path: &str, storage: &StorageConfig) -> Self {
         Delta {
             path: path.to_string(),
+            storage: storage.clone(),
         }
     }
 }
 
 #[async_trait]
 impl Sinks for Delta {
-    async fn write(&self, data: &Vec<RecordBatch>, folder_path: &str) -> Result<(), Error> {
+    async fn write(
+        &self,
+        data: &[RecordBatch],
+        folder_path: &str,
+        options: &WriteOptions,
+    ) -> Result<(), Error> {
         let full_path = format!("{}/{}", self.path, folder_path);
-        delta_sink::write(&full_path, data).await?;
+        delta_sink::write(&full_path, data, options, &self.storage).await?;
         Ok(())
     }
 
+    async fn merge(
+        &self,
+        table_path: &str,
+        data_batches: &[RecordBatch],
+        spec: &MergeSpec,
+    ) -> Result<MergeMetrics, Error> {
+        let metrics = delta_sink::merge(table_path, data_batches, spec, &self.storage).await?;
+        Ok(metrics)
+    }
+
+    /// Updates `target_column` of rows matching on `key_column` and inserts new rows.
     async fn merge_update(
         &self,
         table_path: &str,
-        data_batches: &Vec<RecordBatch>,
+        data_batches: &[RecordBatch],
         key_column: &str,
         target_column: &[&str],
-    ) -> Result<(), Error> {
-        delta_sink::merge_update(table_path, data_batches, key_column, target_column).await?;
-        Ok(())
+    ) -> Result<MergeMetrics, Error> {
+        let assignments: Vec<(String, String)> = target_column
+            .iter()
+            .map(|target| (target.to_string(), format!("source.\"{}\"", target)))
+            .collect();
+        let assignments: Vec<(&str, &str)> = assignments
+            .iter()
+            .map(|(column, value)| (column.as_str(), value.as_str()))
+            .collect();
+
+        let spec = MergeSpec::new(&[key_column])
+            .when_matched_update(None, &assignments)
+            .when_not_matched_insert_all(None);
+        self.merge(table_path, data_batches, &spec).await
+    }
+
+    async fn merge_scd2(
+        &self,
+        table_path: &str,
+        data_batches: &[RecordBatch],
+        keys: &[&str],
+        tracked_columns: &[&str],
+    ) -> Result<MergeMetrics, Error> {
+        let metrics = delta_sink::merge_scd2(
+            table_path,
+            data_batches,
+            keys,
+            tracked_columns,
+            &self.storage,
+        )
+        .await?;
+        Ok(metrics)
+    }
+
+    async fn delete(&self, table_path: &str, predicate: &str) -> Result<usize, Error> {
+        let deleted_rows = delta_sink::delete(table_path, predicate, &self.storage).await?;
+        Ok(deleted_rows)
+    }
+
+    async fn update(
+        &self,
+        table_path: &str,
+        predicate: &str,
+        assignments: &[(&str, &str)],
+    ) -> Result<usize, Error> {
+        let updated_rows =
+            delta_sink::update(table_path, predicate, assignments, &self.storage).await?;
+        Ok(updated_rows)
     }
 }
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use deltalake::arrow::array::RecordBatch;
+use deltalake::arrow::compute::cast;
+use deltalake::arrow::datatypes::{DataType, Field, Schema};
+
+use crate::error::Error;
+
+use super::SchemaMode;
+
+/// Whether values of `from` can be stored in a column of type `to` without loss.
+fn is_safe_widening(from: &DataType, to: &DataType) -> bool {
+    use DataType::*;
+
+    match (from, to) {
+        (Int8, Int16 | Int32 | Int64 | Float32 | Float64) => true,
+        (Int16, Int32 | Int64 | Float32 | Float64) => true,
+        (Int32, Int64 | Float64) => true,
+        (UInt8, Int16 | Int32 | Int64 | UInt16 | UInt32 | UInt64) => true,
+        (UInt16, Int32 | Int64 | UInt32 | UInt64) => true,
+        (UInt32, Int64 | UInt64) => true,
+        (Float32, Float64) => true,
+        (Date32, Date64) => true,
+        (Utf8 | LargeUtf8 | Utf8View, Utf8 | LargeUtf8 | Utf8View) => true,
+        (Binary | LargeBinary | BinaryView, Binary | LargeBinary | BinaryView) => true,
+        // delta stores every timestamp with microsecond precision
+        (Timestamp(_, from_tz), Timestamp(_, to_tz)) => from_tz.is_some() == to_tz.is_some(),
+        (Decimal128(from_precision, from_scale), Decimal128(to_precision, to_scale)) => {
+            to_scale >= from_scale
+                && (*to_precision as i16 - *to_scale as i16)
+                    >= (*from_precision as i16 - *from_scale as i16)
+        }
+        _ => false,
+    }
+}
+
+/// Column level difference between the schema of a table and the schema of incoming data.
+#[derive(Debug, Default)]
+pub(crate) struct SchemaDiff {
+    /// Columns of the data that the table does not have
+    pub added: Vec<String>,
+    /// Columns of the table that the data does not have
+    pub missing: Vec<String>,
+    /// Columns whose type cannot be widened to the table's type, as `column: from -> to`
+    pub type_changed: Vec<String>,
+    /// Columns whose type is widened to the table's type when written
+    pub widened: Vec<(String, DataType)>,
+}
+
+impl SchemaDiff {
+    pub(crate) fn between(table_schema: &Schema, data_schema: &Schema) -> Self {
+        let mut diff = SchemaDiff::default();
+
+        for field in data_schema.fields() {
+            match table_schema.field_with_name(field.name()) {
+                Ok(table_field) if table_field.data_type() == field.data_type() => (),
+                Ok(table_field) if is_safe_widening(field.data_type(), table_field.data_type()) => {
+                    diff.widened
+                        .push((field.name().to_string(), table_field.data_type().clone()));
+                }
+                Ok(table_field) => diff.type_changed.push(format!(
+                    "{}: {} -> {}",
+                    field.name(),
+                    field.data_type(),
+                    table_field.data_type()
+                )),
+                Err(_) => diff.added.push(field.name().to_string()),
+            }
+        }
+
+        for field in table_schema.fields() {
+            if data_schema.field_with_name(field.name()).is_err() {
+                diff.missing.push(field.name().to_string());
+            }
+        }
+
+        diff
+    }
+}
+
+/// Checks data against the schema of an existing table and casts widened columns to the
+/// table's types.
+///
+/// Writes pass `None` as `written_columns`, every column of the data is written and every
+/// column of the table has to be present. Merges pass the columns their clauses write:
+/// other source columns are only used in predicates and table columns may be missing.
+pub(crate) fn enforce_schema(
+    table_schema: &Schema,
+    data: &[RecordBatch],
+    mode: SchemaMode,
+    written_columns: Option<&[String]>,
+) -> Result<Vec<RecordBatch>, Error> {
+    let data_schema = match data.first() {
+        Some(batch) if mode != SchemaMode::OverwriteSchema => batch.schema(),
+        _ => return Ok(data.to_vec()),
+    };
+
+    let mut diff = SchemaDiff::between(table_schema, &data_schema);
+    if let Some(written_columns) = written_columns {
+        diff.added.retain(|column| written_columns.contains(column));
+        diff.missing.clear();
+    }
+
+    let mut problems = vec![];
+    if mode == SchemaMode::Strict && !diff.added.is_empty() {
+        problems.push(format!("added columns: {}", diff.added.join(", ")));
+    }
+    if mode == SchemaMode::Strict && !diff.missing.is_empty() {
+        problems.push(format!("missing columns: {}", diff.missing.join(", ")));
+    }
+    if !diff.type_changed.is_empty() {
+        problems.push(format!(
+            "type changed columns: {}",
+            diff.type_changed.join(", ")
+        ));
+    }
+    if !problems.is_empty() {
+        return Err(Error::SchemaMismatch(problems.join("; ")));
+    }
+
+    if diff.widened.is_empty() {
+        return Ok(data.to_vec());
+    }
+
+    let fields: Vec<Field> = data_schema
+        .fields()
+        .iter()
+        .map(
+            |field| match diff.widened.iter().find(|(name, _)| name == field.name()) {
+                Some((_, data_type)) => field.as_ref().clone().with_data_type(data_type.clone()),
+                None => field.as_ref().clone(),
+            },
+        )
+        .collect();
+    let widened_schema = Arc::new(Schema::new(fields));
+
+    data.iter()
+        .map(|batch| {
+            let columns = batch
+                .columns()
+                .iter()
+                .zip(widened_schema.fields())
+                .map(|(column, field)| cast(column, field.data_type()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(RecordBatch::try_new(widened_schema.clone(), columns)?)
+        })
+        .collect()
+}
@@ -0,0 +1,108 @@
+use async_trait::async_trait;
+
+use crate::error::Error;
+use crate::pipeline::engines::BatchStream;
+
+use super::{delta_stream, Delta};
+
+/// Options of a streaming append to a delta table.
+#[derive(Clone, Debug)]
+pub struct StreamOptions {
+    target_file_size: usize,
+    buffered_batches: usize,
+    partition_by: Vec<String>,
+    table_alias: Option<String>,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        StreamOptions {
+            target_file_size: 100 * 1024 * 1024,
+            buffered_batches: 8,
+            partition_by: vec![],
+            table_alias: None,
+        }
+    }
+}
+
+impl StreamOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Size in bytes of the buffered parquet data at which a file is written, 100MB by default.
+    pub fn target_file_size(mut self, target_file_size: usize) -> Self {
+        self.target_file_size = target_file_size;
+        self
+    }
+
+    /// Number of batches read ahead of the writer, 8 by default.
+    pub fn buffered_batches(mut self, buffered_batches: usize) -> Self {
+        self.buffered_batches = buffered_batches;
+        self
+    }
+
+    /// Partition columns of the table when the stream creates it, see [`super::WriteOptions::partition_by`].
+    pub fn partition_by(mut self, columns: &[&str]) -> Self {
+        self.partition_by = columns.iter().map(|column| column.to_string()).collect();
+        self
+    }
+
+    /// Name the table is mapped to for queries, `delta_<tb_name>` by default.
+    pub fn table_alias(mut self, table_alias: &str) -> Self {
+        self.table_alias = Some(table_alias.to_string());
+        self
+    }
+
+    pub(crate) fn get_target_file_size(&self) -> usize {
+        self.target_file_size
+    }
+
+    pub(crate) fn get_buffered_batches(&self) -> usize {
+        self.buffered_batches
+    }
+
+    pub(crate) fn get_partition_by(&self) -> &[String] {
+        &self.partition_by
+    }
+
+    pub(crate) fn get_table_alias(&self) -> Option<&str> {
+        self.table_alias.as_deref()
+    }
+}
+
+/// Outcome of a streaming write.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamMetrics {
+    pub num_batches: usize,
+    pub num_rows: usize,
+    pub num_files: usize,
+    /// Version committed by the write, `None` when the stream was empty.
+    pub version: Option<i64>,
+}
+
+#[async_trait]
+pub trait StreamSink {
+    /// Appends the batches of a stream to a table in a single commit.
+    async fn write_stream(
+        &self,
+        folder_path: &str,
+        batches: BatchStream,
+        options: &StreamOptions,
+    ) -> Result<StreamMetrics, Error>;
+}
+
+#[async_trait]
+impl StreamSink for Delta {
+    async fn write_stream(
+        &self,
+        folder_path: &str,
+        batches: BatchStream,
+        options: &StreamOptions,
+    ) -> Result<StreamMetrics, Error> {
+        let full_path = format!("{}/{}", self.path, folder_path);
+        let metrics =
+            delta_stream::write_stream(&full_path, batches, options, &self.storage).await?;
+        Ok(metrics)
+    }
+}
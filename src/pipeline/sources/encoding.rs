@@ -0,0 +1,142 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Error;
+
+/// UTF-8 copy of a file in the temporary directory, removed when dropped.
+pub(crate) struct Utf8Copy {
+    path: PathBuf,
+}
+
+impl Utf8Copy {
+    pub(crate) fn path(&self) -> &str {
+        self.path.to_str().unwrap_or_default()
+    }
+}
+
+impl Drop for Utf8Copy {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Size of the chunks read from the source file.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn transcode_latin1(input: &mut dyn Read, output: &mut dyn Write) -> Result<(), Error> {
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        let text: String = buffer[..read].iter().map(|byte| *byte as char).collect();
+        output.write_all(text.as_bytes())?;
+    }
+}
+
+/// Decodes UTF-16 with a byte order mark, little endian when there is none. A code unit or
+/// a surrogate pair split between two chunks is decoded with the next chunk.
+fn transcode_utf16(input: &mut dyn Read, output: &mut dyn Write) -> Result<(), Error> {
+    let mut buffer = vec![0; CHUNK_SIZE];
+    let mut pending = Vec::new();
+    let mut big_endian = None;
+    loop {
+        let read = input.read(&mut buffer)?;
+        let done = read == 0;
+        pending.extend_from_slice(&buffer[..read]);
+
+        let mut start = 0;
+        if big_endian.is_none() {
+            if pending.len() < 2 && !done {
+                continue;
+            }
+            let (bom_len, order) = match pending.as_slice() {
+                [0xfe, 0xff, ..] => (2, true),
+                [0xff, 0xfe, ..] => (2, false),
+                _ => (0, false),
+            };
+            start = bom_len;
+            big_endian = Some(order);
+        }
+        let to_unit = |pair: &[u8]| match big_endian {
+            Some(true) => u16::from_be_bytes([pair[0], pair[1]]),
+            _ => u16::from_le_bytes([pair[0], pair[1]]),
+        };
+
+        let mut end = start + (pending.len() - start) / 2 * 2;
+        if !done && end > start && (0xd800..0xdc00).contains(&to_unit(&pending[end - 2..end])) {
+            end -= 2;
+        }
+        let mut units: Vec<u16> = pending[start..end].chunks(2).map(to_unit).collect();
+        if done && end < pending.len() {
+            units.push(0xfffd);
+        }
+        let text = char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .map_err(|e| Error::Csv(format!("Invalid UTF-16 data: {}", e)))?;
+        output.write_all(text.as_bytes())?;
+
+        if done {
+            return Ok(());
+        }
+        pending.drain(..end);
+    }
+}
+
+/// Copies a local file encoded in `encoding` to a temporary UTF-8 file, since DuckDB only
+/// reads UTF-8 CSV files. Returns `None` when the file already is UTF-8.
+///
+/// The file is decoded chunk by chunk, so only local uncompressed files are supported. The
+/// copy is written with blocking IO, async callers run it on the blocking pool.
+pub(crate) fn to_utf8_copy(path: &str, encoding: &str) -> Result<Option<Utf8Copy>, Error> {
+    let transcode: fn(&mut dyn Read, &mut dyn Write) -> Result<(), Error> =
+        match encoding.to_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => return Ok(None),
+            "latin-1" | "latin1" | "iso-8859-1" => transcode_latin1,
+            "utf-16" | "utf16" => transcode_utf16,
+            _ => {
+                return Err(Error::Csv(format!(
+                    "Unsupported encoding {}, expected utf-8, utf-16 or latin-1",
+                    encoding
+                )))
+            }
+        };
+
+    let local_path = path.strip_prefix("file://").unwrap_or(path);
+    let lower_path = local_path.to_lowercase();
+    if local_path.contains("://")
+        || local_path.contains('*')
+        || lower_path.ends_with(".gz")
+        || lower_path.ends_with(".zst")
+    {
+        return Err(Error::Csv(format!(
+            "Encoding {} can only be read from a single local uncompressed file, got {}",
+            encoding, path
+        )));
+    }
+
+    let mut input = File::open(local_path)?;
+
+    // The extension is kept, the default delimiter of the read depends on it
+    let extension = lower_path.rsplit('.').next().unwrap_or("csv");
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_nanos())
+        .unwrap_or_default();
+    let copy = Utf8Copy {
+        path: std::env::temp_dir().join(format!(
+            "duckdelta_{}_{}.{}",
+            std::process::id(),
+            nanos,
+            extension
+        )),
+    };
+    // The copy is removed when the transcoding fails half way
+    let mut output = BufWriter::new(File::create(&copy.path)?);
+    transcode(&mut input, &mut output)?;
+    output.flush()?;
+    Ok(Some(copy))
+}
@@ -1,14 +1,34 @@
+use std::sync::{Arc, Mutex};
+
 use async_trait::async_trait;
 use deltalake::arrow::array::RecordBatch;
+use deltalake::datafusion::prelude::SessionContext;
+use deltalake::DeltaTable;
 use duckdb::Connection;
+use futures::StreamExt;
+use tokio::sync::mpsc;
+use tokio::task;
 
 use crate::error::Error;
 
+use super::engines::{stream_query, BatchStream};
+use super::sinks::delta_sink;
+use super::storage::StorageConfig;
+
+mod encoding;
+pub mod options;
+
+use encoding::Utf8Copy;
+use options::{quote_identifier, quote_literal};
+pub use options::{
+    Compression, CsvOptions, DeltaReadOptions, JsonFormat, JsonOptions, JsonRecords,
+};
+
 pub enum SourcesType<'a> {
-    Csv(&'a str),
-    Json(&'a str),
+    Csv(&'a str, &'a CsvOptions),
+    Json(&'a str, &'a JsonOptions),
     Parquet(&'a str),
-    Delta(&'a str),
+    Delta(&'a str, &'a DeltaReadOptions),
 }
 
 #[async_trait]
@@ -17,37 +37,297 @@ pub trait Sources {
 }
 
 impl<'a> SourcesType<'a> {
-    async fn read_by_duckdb(tb_path: &str) -> Result<Vec<RecordBatch>, Error> {
-        let sql = format!("select * from read_csv('{}')", tb_path);
-        let conn = Connection::open_in_memory()?;
+    /// Opens a fresh connection, with the extension and the secrets of `storage` when
+    /// `tb_path` is remote.
+    async fn open_duckdb(tb_path: &str, storage: &StorageConfig) -> Result<Connection, Error> {
+        let extension = match tb_path.split_once("://").map(|(scheme, _)| scheme) {
+            Some("s3" | "s3a" | "gs") => Some("httpfs"),
+            Some("az" | "abfs" | "abfss") => Some("azure"),
+            _ => None,
+        };
+        let setup_query = match extension {
+            Some(extension) => Some(format!(
+                "INSTALL {0}; LOAD {0}; {1}",
+                extension,
+                storage.to_duckdb_secrets().await
+            )),
+            None => None,
+        };
 
-        let mut stmt = conn.prepare(&sql)?;
-        let arrow_result = stmt.query_arrow([])?;
-        let batches = arrow_result.collect::<Vec<RecordBatch>>();
+        // Installing the extension downloads it, which must not block the runtime
+        task::spawn_blocking(move || {
+            let conn = Connection::open_in_memory()?;
+            if let Some(setup_query) = setup_query {
+                conn.execute_batch(&setup_query)?;
+            }
+            Ok(conn)
+        })
+        .await?
+    }
 
-        Ok(batches)
+    /// Runs `sql` on `conn` on the blocking pool. When a rejects table is given, the rows
+    /// DuckDB stored in it while running `sql` are returned as well.
+    async fn read_by_duckdb(
+        conn: Connection,
+        sql: &str,
+        rejects_table: Option<&str>,
+    ) -> Result<(Vec<RecordBatch>, Option<Vec<RecordBatch>>), Error> {
+        let sql = sql.to_string();
+        let rejects_table = rejects_table.map(quote_identifier);
+
+        task::spawn_blocking(move || {
+            let mut stmt = conn.prepare(&sql)?;
+            let arrow_result = stmt.query_arrow([])?;
+            let batches = arrow_result.collect::<Vec<RecordBatch>>();
+
+            let rejected_batches = match rejects_table {
+                Some(rejects_table) => {
+                    let mut stmt = conn.prepare(&format!("select * from {}", rejects_table))?;
+                    let arrow_result = stmt.query_arrow([])?;
+                    Some(arrow_result.collect::<Vec<RecordBatch>>())
+                }
+                None => None,
+            };
+
+            Ok((batches, rejected_batches))
+        })
+        .await?
     }
 
-    async fn read(&self) -> Result<Vec<RecordBatch>, Error> {
-        match self {
-            SourcesType::Csv(path) => {
-                let path_splitted: Vec<&str> = path.split(".").collect();
-                let record_batches = match path_splitted.last().unwrap().to_lowercase().as_str() {
-                    "csv" => Self::read_by_duckdb(path).await?,
+    /// Resolves a parquet path to something `read_parquet` can scan: files and
+    /// glob patterns are kept as they are, directories are scanned recursively.
+    fn parquet_scan_path(path: &str) -> String {
+        if path.contains('*') || path.to_lowercase().ends_with(".parquet") {
+            path.to_string()
+        } else {
+            format!("{}/**/*.parquet", path.trim_end_matches('/'))
+        }
+    }
+
+    /// Lists the parquet files `path` resolves to on the blocking pool. Files with a folder
+    /// or a name starting with `_` or `.` below the scanned directory are skipped, like the
+    /// `_delta_log` checkpoints, `_temporary` folders of writers and hidden files.
+    async fn parquet_files(conn: Connection, path: &str) -> Result<Vec<String>, Error> {
+        let pattern = Self::parquet_scan_path(path);
+        let glob_start = match pattern.find('*') {
+            Some(glob_start) => glob_start,
+            None => return Ok(vec![pattern]),
+        };
+        let root = pattern[..glob_start]
+            .rfind(['/', '\\'])
+            .map_or(String::new(), |end| pattern[..=end].to_string());
+
+        let files = task::spawn_blocking(move || {
+            let mut stmt = conn.prepare("select file from glob(?) order by file")?;
+            let files = stmt
+                .query_map([&pattern], |row| row.get::<_, String>(0))?
+                .collect::<Result<Vec<String>, _>>()?;
+            Ok::<_, Error>(files)
+        })
+        .await??;
+
+        let files: Vec<String> = files
+            .into_iter()
+            .filter(|file| {
+                file.strip_prefix(root.as_str())
+                    .unwrap_or(file)
+                    .split(['/', '\\'])
+                    .all(|segment| !segment.starts_with('_') && !segment.starts_with('.'))
+            })
+            .collect();
+        if files.is_empty() {
+            return Err(Error::DuckDB(format!("No parquet files found in {}", path)));
+        }
+        Ok(files)
+    }
+
+    /// Returns the file extension, ignoring a trailing `.gz` or `.zst` compression suffix.
+    fn uncompressed_extension(path: &str) -> String {
+        let lower_path = path.to_lowercase();
+        let path = lower_path
+            .strip_suffix(".gz")
+            .or_else(|| lower_path.strip_suffix(".zst"))
+            .unwrap_or(&lower_path);
+        path.split(".").last().unwrap_or_default().to_string()
+    }
+
+    /// Builds the DuckDB query reading a CSV or JSON source, `None` for parquet sources, whose
+    /// files are listed first, and delta tables.
+    fn duckdb_sql(&self) -> Result<Option<String>, Error> {
+        let sql = match self {
+            SourcesType::Csv(path, options) => {
+                let default_delimiter = match Self::uncompressed_extension(path).as_str() {
+                    "csv" => None,
+                    "tsv" => Some("\t"),
+                    _ if path.contains('*') => None,
                     _ => return Err(Error::UnsupportedFormat(path.to_string())),
                 };
-                Ok(record_batches)
+
+                format!(
+                    "select * from read_csv({}{})",
+                    quote_literal(path),
+                    options.to_sql_params(default_delimiter)
+                )
+            }
+            SourcesType::Json(path, options) => {
+                if !path.contains('*')
+                    && !matches!(
+                        Self::uncompressed_extension(path).as_str(),
+                        "json" | "ndjson" | "jsonl"
+                    )
+                {
+                    return Err(Error::UnsupportedFormat(path.to_string()));
+                }
+
+                format!(
+                    "select * from read_json({}{})",
+                    quote_literal(path),
+                    options.to_sql_params()
+                )
             }
-            SourcesType::Json(_) => unimplemented!(),
-            SourcesType::Parquet(_) => unimplemented!(),
-            SourcesType::Delta(_) => unimplemented!(),
+            SourcesType::Parquet(_) | SourcesType::Delta(_, _) => return Ok(None),
+        };
+        Ok(Some(sql))
+    }
+
+    /// Builds the DuckDB query reading the listed parquet files.
+    fn parquet_sql(files: &[String]) -> String {
+        let files: Vec<String> = files.iter().map(|file| quote_literal(file)).collect();
+        // Hive partition keys become columns and drifting schemas are merged by name
+        format!(
+            "select * from read_parquet([{}], hive_partitioning = true, union_by_name = true)",
+            files.join(", ")
+        )
+    }
+
+    /// Builds the DuckDB query of a file source, reading CSV files that are not UTF-8 from
+    /// a transcoded copy and parquet sources from the files listed on `conn`. The copy has
+    /// to be kept until the query has run.
+    async fn duckdb_sql_utf8(&self, conn: Connection) -> Result<(String, Option<Utf8Copy>), Error> {
+        let copy = match self {
+            SourcesType::Csv(path, options) => match options.get_encoding() {
+                Some(encoding) => {
+                    let (path, encoding) = (path.to_string(), encoding.to_string());
+                    task::spawn_blocking(move || encoding::to_utf8_copy(&path, &encoding)).await??
+                }
+                None => None,
+            },
+            _ => None,
+        };
+
+        let sql = match (self, &copy) {
+            (SourcesType::Csv(_, options), Some(copy)) => {
+                SourcesType::Csv(copy.path(), options).duckdb_sql()?
+            }
+            (SourcesType::Parquet(path), _) => {
+                Some(Self::parquet_sql(&Self::parquet_files(conn, path).await?))
+            }
+            _ => self.duckdb_sql()?,
+        };
+        Ok((sql.unwrap_or_default(), copy))
+    }
+
+    fn path(&self) -> &str {
+        match self {
+            SourcesType::Csv(path, _)
+            | SourcesType::Json(path, _)
+            | SourcesType::Parquet(path)
+            | SourcesType::Delta(path, _) => path,
         }
     }
+
+    async fn open_delta_table(
+        uri: &str,
+        options: &DeltaReadOptions,
+        storage: &StorageConfig,
+    ) -> Result<DeltaTable, Error> {
+        delta_sink::open_delta_table_as_of(uri, options.version(), options.timestamp(), storage)
+            .await
+    }
+
+    /// Reads the source, together with the rejected rows for CSV reads that keep a rejects table.
+    pub(crate) async fn read_with_rejects(
+        &self,
+        storage: &StorageConfig,
+    ) -> Result<(Vec<RecordBatch>, Option<Vec<RecordBatch>>), Error> {
+        match self {
+            SourcesType::Delta(uri, options) => {
+                let table = Self::open_delta_table(uri, options, storage).await?;
+                let ctx = SessionContext::new();
+                let batches = ctx.read_table(Arc::new(table))?.collect().await?;
+                Ok((batches, None))
+            }
+            _ => {
+                let conn = Self::open_duckdb(self.path(), storage).await?;
+                let (sql, _copy) = self.duckdb_sql_utf8(conn.try_clone()?).await?;
+                let rejects_table = match self {
+                    SourcesType::Csv(_, options) => options.get_rejects_table(),
+                    _ => None,
+                };
+                Self::read_by_duckdb(conn, &sql, rejects_table).await
+            }
+        }
+    }
+
+    /// Reads the source batch by batch, at most `capacity` batches wait while the consumer
+    /// is busy, see [`stream_query`] for file sources. Rejected CSV rows are not kept.
+    pub(crate) async fn stream(
+        &self,
+        capacity: usize,
+        storage: &StorageConfig,
+    ) -> Result<BatchStream, Error> {
+        match self {
+            SourcesType::Delta(uri, options) => {
+                let table = Self::open_delta_table(uri, options, storage).await?;
+                let ctx = SessionContext::new();
+                let mut batches = ctx.read_table(Arc::new(table))?.execute_stream().await?;
+
+                let (sender, receiver) = mpsc::channel(capacity.max(1));
+                tokio::spawn(async move {
+                    while let Some(batch) = batches.next().await {
+                        if sender.send(batch.map_err(Error::from)).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok(receiver)
+            }
+            _ => {
+                let conn = Self::open_duckdb(self.path(), storage).await?;
+                let (sql, copy) = self.duckdb_sql_utf8(conn.try_clone()?).await?;
+                let mut batches = stream_query(Arc::new(Mutex::new(conn)), sql, capacity);
+
+                let copy = match copy {
+                    Some(copy) => copy,
+                    None => return Ok(batches),
+                };
+                // The copy is removed once the query sent its last batch
+                let (sender, receiver) = mpsc::channel(capacity.max(1));
+                tokio::spawn(async move {
+                    let _copy = copy;
+                    while let Some(batch) = batches.recv().await {
+                        if sender.send(batch).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok(receiver)
+            }
+        }
+    }
+
+    pub(crate) async fn read_with_storage(
+        &self,
+        storage: &StorageConfig,
+    ) -> Result<Vec<RecordBatch>, Error> {
+        let (batches, _) = self.read_with_rejects(storage).await?;
+        Ok(batches)
+    }
 }
 
 #[async_trait]
 impl<'a> Sources for SourcesType<'a> {
     async fn read_data(&self) -> Result<Vec<RecordBatch>, Error> {
-        self.read().await
+        self.read_with_storage(&StorageConfig::default()).await
     }
 }
@@ -0,0 +1,354 @@
+/// Quotes a value as a DuckDB string literal.
+pub(crate) fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace("'", "''"))
+}
+
+/// Quotes a name as a DuckDB identifier.
+pub(crate) fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace("\"", "\"\""))
+}
+
+/// Compression codec of an input file. `Auto` lets DuckDB detect it from the file extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Auto,
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Compression::Auto => "auto",
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+/// Layout of a JSON file: one document per line or a single top level array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonFormat {
+    Auto,
+    NewlineDelimited,
+    Array,
+}
+
+/// Whether JSON objects are unpacked into columns (`Records`) or read as a single `json` column (`Values`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JsonRecords {
+    Auto,
+    Records,
+    Values,
+}
+
+/// Options passed to DuckDB `read_json`.
+#[derive(Clone, Debug, Default)]
+pub struct JsonOptions {
+    columns: Vec<(String, String)>,
+    maximum_depth: Option<i64>,
+    format: Option<JsonFormat>,
+    records: Option<JsonRecords>,
+    compression: Option<Compression>,
+}
+
+impl JsonOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares the type of a column, e.g. `column("id", "BIGINT")`. When at least one column
+    /// is declared, schema inference is disabled and only the declared columns are read.
+    pub fn column(mut self, name: &str, data_type: &str) -> Self {
+        self.columns.push((name.to_string(), data_type.to_string()));
+        self
+    }
+
+    /// Maximum nesting depth up to which structs and lists are inferred, deeper values stay `JSON`.
+    pub fn maximum_depth(mut self, depth: i64) -> Self {
+        self.maximum_depth = Some(depth);
+        self
+    }
+
+    pub fn format(mut self, format: JsonFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn records(mut self, records: JsonRecords) -> Self {
+        self.records = Some(records);
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Renders the options as named `read_json` parameters, each prefixed with a comma.
+    pub(crate) fn to_sql_params(&self) -> String {
+        let mut params = String::new();
+
+        if !self.columns.is_empty() {
+            let columns: Vec<String> = self
+                .columns
+                .iter()
+                .map(|(name, data_type)| {
+                    format!("{}: {}", quote_literal(name), quote_literal(data_type))
+                })
+                .collect();
+            params.push_str(&format!(", columns = {{{}}}", columns.join(", ")));
+        }
+
+        if let Some(depth) = self.maximum_depth {
+            params.push_str(&format!(", maximum_depth = {}", depth));
+        }
+
+        if let Some(format) = self.format {
+            let format = match format {
+                JsonFormat::Auto => "auto",
+                JsonFormat::NewlineDelimited => "newline_delimited",
+                JsonFormat::Array => "array",
+            };
+            params.push_str(&format!(", format = '{}'", format));
+        }
+
+        if let Some(records) = self.records {
+            let records = match records {
+                JsonRecords::Auto => "auto",
+                JsonRecords::Records => "true",
+                JsonRecords::Values => "false",
+            };
+            params.push_str(&format!(", records = '{}'", records));
+        }
+
+        if let Some(compression) = self.compression {
+            params.push_str(&format!(", compression = '{}'", compression.as_str()));
+        }
+
+        params
+    }
+}
+
+/// Snapshot of a delta table to read, the latest version when nothing is set.
+#[derive(Clone, Debug, Default)]
+pub struct DeltaReadOptions {
+    version: Option<i64>,
+    timestamp: Option<String>,
+}
+
+impl DeltaReadOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn as_of_version(mut self, version: i64) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Reads the latest version committed at or before an RFC 3339 timestamp,
+    /// e.g. `2024-12-01T00:00:00Z`.
+    pub fn as_of_timestamp(mut self, timestamp: &str) -> Self {
+        self.timestamp = Some(timestamp.to_string());
+        self
+    }
+
+    pub(crate) fn version(&self) -> Option<i64> {
+        self.version
+    }
+
+    pub(crate) fn timestamp(&self) -> Option<&str> {
+        self.timestamp.as_deref()
+    }
+}
+
+/// Options passed to DuckDB `read_csv`, anything left unset is auto detected.
+#[derive(Clone, Debug, Default)]
+pub struct CsvOptions {
+    delimiter: Option<String>,
+    quote: Option<String>,
+    escape: Option<String>,
+    header: Option<bool>,
+    null_strings: Vec<String>,
+    date_format: Option<String>,
+    timestamp_format: Option<String>,
+    types: Vec<(String, String)>,
+    skip_rows: Option<usize>,
+    encoding: Option<String>,
+    sample_size: Option<i64>,
+    ignore_errors: bool,
+    rejects_table: Option<String>,
+    compression: Option<Compression>,
+}
+
+impl CsvOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn delimiter(mut self, delimiter: &str) -> Self {
+        self.delimiter = Some(delimiter.to_string());
+        self
+    }
+
+    pub fn quote(mut self, quote: &str) -> Self {
+        self.quote = Some(quote.to_string());
+        self
+    }
+
+    pub fn escape(mut self, escape: &str) -> Self {
+        self.escape = Some(escape.to_string());
+        self
+    }
+
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = Some(header);
+        self
+    }
+
+    /// Adds a string that is read as `NULL`, can be called multiple times.
+    pub fn null_string(mut self, null_string: &str) -> Self {
+        self.null_strings.push(null_string.to_string());
+        self
+    }
+
+    /// Format of date columns, e.g. `%d.%m.%Y`.
+    pub fn date_format(mut self, date_format: &str) -> Self {
+        self.date_format = Some(date_format.to_string());
+        self
+    }
+
+    /// Format of timestamp columns, e.g. `%d.%m.%Y %H:%M:%S`.
+    pub fn timestamp_format(mut self, timestamp_format: &str) -> Self {
+        self.timestamp_format = Some(timestamp_format.to_string());
+        self
+    }
+
+    /// Overrides the detected type of a column, e.g. `column_type("zip", "VARCHAR")`.
+    pub fn column_type(mut self, name: &str, data_type: &str) -> Self {
+        self.types.push((name.to_string(), data_type.to_string()));
+        self
+    }
+
+    /// Number of lines skipped at the top of the file, before the header.
+    pub fn skip_rows(mut self, skip_rows: usize) -> Self {
+        self.skip_rows = Some(skip_rows);
+        self
+    }
+
+    /// Encoding of the file, one of `utf-8`, `utf-16` or `latin-1`. DuckDB only reads UTF-8,
+    /// other encodings are transcoded to a temporary copy of the file first, which needs a
+    /// single local uncompressed file.
+    pub fn encoding(mut self, encoding: &str) -> Self {
+        self.encoding = Some(encoding.to_string());
+        self
+    }
+
+    /// Number of rows sampled for dialect and type detection, `-1` samples the whole file.
+    pub fn sample_size(mut self, sample_size: i64) -> Self {
+        self.sample_size = Some(sample_size);
+        self
+    }
+
+    /// Skips rows that cannot be parsed instead of failing the read.
+    pub fn ignore_errors(mut self, ignore_errors: bool) -> Self {
+        self.ignore_errors = ignore_errors;
+        self
+    }
+
+    /// Skips rows that cannot be parsed and keeps them in a rejects table,
+    /// see [`Pipeline::rejected_rows`](crate::pipeline::Pipeline::rejected_rows).
+    pub fn rejects_table(mut self, rejects_table: &str) -> Self {
+        self.rejects_table = Some(rejects_table.to_string());
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    pub(crate) fn get_encoding(&self) -> Option<&str> {
+        self.encoding.as_deref()
+    }
+
+    pub(crate) fn get_rejects_table(&self) -> Option<&str> {
+        self.rejects_table.as_deref()
+    }
+
+    /// Renders the options as named `read_csv` parameters, each prefixed with a comma.
+    /// `default_delimiter` is used when no delimiter was set, e.g. a tab for `.tsv` files.
+    pub(crate) fn to_sql_params(&self, default_delimiter: Option<&str>) -> String {
+        let mut params = String::new();
+
+        if let Some(delimiter) = self.delimiter.as_deref().or(default_delimiter) {
+            params.push_str(&format!(", delim = {}", quote_literal(delimiter)));
+        }
+
+        if let Some(quote) = &self.quote {
+            params.push_str(&format!(", quote = {}", quote_literal(quote)));
+        }
+
+        if let Some(escape) = &self.escape {
+            params.push_str(&format!(", escape = {}", quote_literal(escape)));
+        }
+
+        if let Some(header) = self.header {
+            params.push_str(&format!(", header = {}", header));
+        }
+
+        if !self.null_strings.is_empty() {
+            let null_strings: Vec<String> =
+                self.null_strings.iter().map(|s| quote_literal(s)).collect();
+            params.push_str(&format!(", nullstr = [{}]", null_strings.join(", ")));
+        }
+
+        if let Some(date_format) = &self.date_format {
+            params.push_str(&format!(", dateformat = {}", quote_literal(date_format)));
+        }
+
+        if let Some(timestamp_format) = &self.timestamp_format {
+            params.push_str(&format!(
+                ", timestampformat = {}",
+                quote_literal(timestamp_format)
+            ));
+        }
+
+        if !self.types.is_empty() {
+            let types: Vec<String> = self
+                .types
+                .iter()
+                .map(|(name, data_type)| {
+                    format!("{}: {}", quote_literal(name), quote_literal(data_type))
+                })
+                .collect();
+            params.push_str(&format!(", types = {{{}}}", types.join(", ")));
+        }
+
+        if let Some(skip_rows) = self.skip_rows {
+            params.push_str(&format!(", skip = {}", skip_rows));
+        }
+
+        if let Some(sample_size) = self.sample_size {
+            params.push_str(&format!(", sample_size = {}", sample_size));
+        }
+
+        if let Some(rejects_table) = &self.rejects_table {
+            params.push_str(&format!(
+                ", store_rejects = true, rejects_table = {}",
+                quote_literal(rejects_table)
+            ));
+        } else if self.ignore_errors {
+            params.push_str(", ignore_errors = true");
+        }
+
+        if let Some(compression) = self.compression {
+            params.push_str(&format!(", compression = '{}'", compression.as_str()));
+        }
+
+        params
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+use std::fs;
+
+use aws_config::BehaviorVersion;
+use deltalake::aws::constants::{
+    AWS_ACCESS_KEY_ID, AWS_ALLOW_HTTP, AWS_ENDPOINT_URL, AWS_FORCE_CREDENTIAL_LOAD, AWS_PROFILE,
+    AWS_REGION, AWS_S3_ADDRESSING_STYLE, AWS_S3_ALLOW_UNSAFE_RENAME, AWS_SECRET_ACCESS_KEY,
+    AWS_SESSION_TOKEN,
+};
+
+use serde_json::json;
+
+use crate::pipeline::sources::options::quote_literal;
+
+/// How buckets are addressed, `Path` is `https://endpoint/bucket/key` and `VirtualHosted`
+/// is `https://bucket.endpoint/key`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UrlStyle {
+    #[default]
+    Path,
+    VirtualHosted,
+}
+
+/// Static S3 credentials, or the HMAC key of a GCS service account.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Credentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: Option<String>,
+}
+
+impl Credentials {
+    pub fn new(access_key_id: &str, secret_access_key: &str) -> Self {
+        Credentials {
+            access_key_id: access_key_id.to_string(),
+            secret_access_key: secret_access_key.to_string(),
+            session_token: None,
+        }
+    }
+
+    /// Token of temporary credentials, e.g. from an assumed role.
+    pub fn session_token(mut self, session_token: &str) -> Self {
+        self.session_token = Some(session_token.to_string());
+        self
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Account key of the Azurite emulator, the same for every installation.
+const AZURITE_ACCOUNT_NAME: &str = "devstoreaccount1";
+const AZURITE_ACCOUNT_KEY: &str =
+    "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+const AZURITE_ENDPOINT: &str = "http://127.0.0.1:10000";
+
+/// Azure Blob Storage and ADLS Gen2 settings of `az://` and `abfss://` tables.
+///
+/// Without an access key or SAS token the Azure credential chain is used, e.g. the
+/// `AZURE_*` variables of a service principal or `az login`.
+#[derive(Clone, Default)]
+pub struct AzureConfig {
+    account_name: Option<String>,
+    access_key: Option<String>,
+    sas_token: Option<String>,
+    endpoint: Option<String>,
+    use_emulator: bool,
+}
+
+impl std::fmt::Debug for AzureConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AzureConfig")
+            .field("account_name", &self.account_name)
+            .field("access_key", &self.access_key.as_ref().map(|_| "***"))
+            .field("sas_token", &self.sas_token.as_ref().map(|_| "***"))
+            .field("endpoint", &self.endpoint)
+            .field("use_emulator", &self.use_emulator)
+            .finish()
+    }
+}
+
+impl AzureConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn account_name(mut self, account_name: &str) -> Self {
+        self.account_name = Some(account_name.to_string());
+        self
+    }
+
+    pub fn access_key(mut self, access_key: &str) -> Self {
+        self.access_key = Some(access_key.to_string());
+        self
+    }
+
+    pub fn sas_token(mut self, sas_token: &str) -> Self {
+        self.sas_token = Some(sas_token.trim_start_matches('?').to_string());
+        self
+    }
+
+    /// Blob endpoint without the account, e.g. `http://localhost:10000` for Azurite.
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// Connects to a local Azurite with its well-known account, unless another account
+    /// is set.
+    pub fn use_emulator(mut self, use_emulator: bool) -> Self {
+        self.use_emulator = use_emulator;
+        self
+    }
+
+    fn get_account_name(&self) -> Option<&str> {
+        match (&self.account_name, self.use_emulator) {
+            (Some(account_name), _) => Some(account_name),
+            (None, true) => Some(AZURITE_ACCOUNT_NAME),
+            (None, false) => None,
+        }
+    }
+
+    fn get_access_key(&self) -> Option<&str> {
+        match (
+            &self.access_key,
+            self.use_emulator && self.account_name.is_none(),
+        ) {
+            (Some(access_key), _) => Some(access_key),
+            (None, true) => Some(AZURITE_ACCOUNT_KEY),
+            (None, false) => None,
+        }
+    }
+
+    fn get_endpoint(&self) -> Option<&str> {
+        match (&self.endpoint, self.use_emulator) {
+            (Some(endpoint), _) => Some(endpoint),
+            (None, true) => Some(AZURITE_ENDPOINT),
+            (None, false) => None,
+        }
+    }
+
+    /// Custom endpoints address the account in the path, like Azurite does.
+    fn blob_endpoint(&self) -> Option<String> {
+        let endpoint = self.get_endpoint()?;
+        match self.get_account_name() {
+            Some(account_name) => Some(format!("{}/{}", endpoint, account_name)),
+            None => Some(endpoint.to_string()),
+        }
+    }
+
+    /// The deltalake storage options.
+    fn to_storage_options(&self) -> HashMap<String, String> {
+        let mut options = HashMap::new();
+
+        if let Some(account_name) = self.get_account_name() {
+            options.insert(
+                "azure_storage_account_name".to_string(),
+                account_name.to_string(),
+            );
+        }
+        if let Some(access_key) = self.get_access_key() {
+            options.insert(
+                "azure_storage_account_key".to_string(),
+                access_key.to_string(),
+            );
+        }
+        if let Some(sas_token) = &self.sas_token {
+            options.insert("azure_storage_sas_token".to_string(), sas_token.clone());
+        }
+        if let Some(blob_endpoint) = self.blob_endpoint() {
+            options.insert(
+                "azure_allow_http".to_string(),
+                blob_endpoint.starts_with("http://").to_string(),
+            );
+            options.insert("azure_storage_endpoint".to_string(), blob_endpoint);
+        }
+
+        options
+    }
+
+    /// `CREATE SECRET` statement, a connection string when there is a key and the
+    /// credential chain otherwise.
+    fn to_duckdb_secret(&self, name: &str, scope: Option<&str>) -> String {
+        let mut params = vec!["TYPE AZURE".to_string()];
+
+        let credential = match (self.get_access_key(), &self.sas_token) {
+            (Some(access_key), _) => Some(format!("AccountKey={}", access_key)),
+            (None, Some(sas_token)) => Some(format!("SharedAccessSignature={}", sas_token)),
+            (None, None) => None,
+        };
+        match credential {
+            Some(credential) => {
+                let mut connection_string = vec![];
+                if let Some(endpoint) = self.get_endpoint() {
+                    let protocol = endpoint.split("://").next().unwrap_or("https");
+                    connection_string.push(format!("DefaultEndpointsProtocol={}", protocol));
+                }
+                if let Some(account_name) = self.get_account_name() {
+                    connection_string.push(format!("AccountName={}", account_name));
+                }
+                connection_string.push(credential);
+                if let Some(blob_endpoint) = self.blob_endpoint() {
+                    connection_string.push(format!("BlobEndpoint={}", blob_endpoint));
+                }
+                params.push(format!(
+                    "CONNECTION_STRING {}",
+                    quote_literal(&connection_string.join(";"))
+                ));
+            }
+            None => {
+                params.push("PROVIDER CREDENTIAL_CHAIN".to_string());
+                if let Some(account_name) = self.get_account_name() {
+                    params.push(format!("ACCOUNT_NAME {}", quote_literal(account_name)));
+                }
+            }
+        }
+        if let Some(scope) = scope {
+            params.push(format!("SCOPE {}", quote_literal(scope)));
+        }
+
+        format!("CREATE OR REPLACE SECRET {} ({});", name, params.join(", "))
+    }
+}
+
+/// Google Cloud Storage settings of `gs://` tables.
+///
+/// deltalake authenticates with a service account, DuckDB reads through the S3
+/// compatible API of GCS with an HMAC key of it. Without a service account the
+/// application default credentials are used.
+#[derive(Clone, Default)]
+pub struct GcsConfig {
+    service_account_path: Option<String>,
+    service_account_key: Option<String>,
+    hmac_credentials: Option<Credentials>,
+    endpoint: Option<String>,
+}
+
+impl std::fmt::Debug for GcsConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GcsConfig")
+            .field("service_account_path", &self.service_account_path)
+            .field(
+                "service_account_key",
+                &self.service_account_key.as_ref().map(|_| "***"),
+            )
+            .field("hmac_credentials", &self.hmac_credentials)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl GcsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Path of the JSON key file of the service account.
+    pub fn service_account_path(mut self, service_account_path: &str) -> Self {
+        self.service_account_path = Some(service_account_path.to_string());
+        self
+    }
+
+    /// JSON key of the service account.
+    pub fn service_account_key(mut self, service_account_key: &str) -> Self {
+        self.service_account_key = Some(service_account_key.to_string());
+        self
+    }
+
+    /// HMAC key DuckDB signs its requests with.
+    pub fn hmac_credentials(mut self, hmac_credentials: Credentials) -> Self {
+        self.hmac_credentials = Some(hmac_credentials);
+        self
+    }
+
+    /// Endpoint of an emulator like fake-gcs-server, e.g. `http://localhost:4443`.
+    /// Requests to it are not authenticated unless a service account is set.
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// The service account key with `endpoint` set in it, object_store only takes a custom
+    /// endpoint from a key. Without a service account an unauthenticated key is used, `None`
+    /// when the service account can not be read as a JSON key.
+    fn key_with_endpoint(&self, endpoint: &str) -> Option<String> {
+        let service_account_key = match (&self.service_account_key, &self.service_account_path) {
+            (Some(service_account_key), _) => Some(service_account_key.clone()),
+            (None, Some(service_account_path)) => fs::read_to_string(service_account_path).ok(),
+            (None, None) => None,
+        };
+        let mut key = match service_account_key {
+            Some(service_account_key) => serde_json::from_str(&service_account_key).ok()?,
+            None => json!({
+                "client_email": "",
+                "private_key": "",
+                "private_key_id": "",
+                "disable_oauth": true,
+            }),
+        };
+        key.as_object_mut()?
+            .insert("gcs_base_url".to_string(), json!(endpoint));
+        Some(key.to_string())
+    }
+
+    /// The deltalake storage options.
+    fn to_storage_options(&self) -> HashMap<String, String> {
+        let mut options = HashMap::new();
+
+        // A key object_store can not read is passed on as it is, so that it reports the error
+        if let Some(key) = self
+            .endpoint
+            .as_deref()
+            .and_then(|endpoint| self.key_with_endpoint(endpoint))
+        {
+            options.insert("google_service_account_key".to_string(), key);
+            return options;
+        }
+        if let Some(service_account_path) = &self.service_account_path {
+            options.insert(
+                "google_service_account".to_string(),
+                service_account_path.clone(),
+            );
+        }
+        if let Some(service_account_key) = &self.service_account_key {
+            options.insert(
+                "google_service_account_key".to_string(),
+                service_account_key.clone(),
+            );
+        }
+
+        options
+    }
+
+    /// `CREATE SECRET` statement.
+    fn to_duckdb_secret(&self, name: &str, scope: Option<&str>) -> String {
+        let mut params = vec!["TYPE GCS".to_string()];
+
+        if let Some(credentials) = &self.hmac_credentials {
+            params.push(format!(
+                "KEY_ID {}",
+                quote_literal(&credentials.access_key_id)
+            ));
+            params.push(format!(
+                "SECRET {}",
+                quote_literal(&credentials.secret_access_key)
+            ));
+        }
+        if let Some(endpoint) = &self.endpoint {
+            params.push(format!(
+                "ENDPOINT {}",
+                quote_literal(endpoint_host(endpoint))
+            ));
+            params.push(format!("USE_SSL {}", !endpoint.starts_with("http://")));
+            params.push("URL_STYLE 'path'".to_string());
+        }
+        if let Some(scope) = scope {
+            params.push(format!("SCOPE {}", quote_literal(scope)));
+        }
+
+        format!("CREATE OR REPLACE SECRET {} ({});", name, params.join(", "))
+    }
+}
+
+/// The host of an endpoint, DuckDB sets TLS with USE_SSL.
+fn endpoint_host(endpoint: &str) -> &str {
+    endpoint
+        .strip_prefix("https://")
+        .or_else(|| endpoint.strip_prefix("http://"))
+        .unwrap_or(endpoint)
+        .trim_end_matches('/')
+}
+
+/// Object store settings shared by the DuckDB secrets of an engine and the deltalake
+/// storage options of the sinks, so that both reach the same buckets the same way.
+///
+/// The settings set on the config itself are those of S3. Azure and GCS have their own
+/// settings, set with [`StorageConfig::azure`] and [`StorageConfig::gcs`].
+///
+/// S3 settings left unset are loaded from the AWS environment (`~/.aws`, `AWS_*`
+/// variables) unless [`StorageConfig::use_environment`] turns that off. Missing settings
+/// are not an error, local pipelines work without any AWS configuration.
+#[derive(Clone, Debug)]
+pub struct StorageConfig {
+    endpoint: Option<String>,
+    region: Option<String>,
+    credentials: Option<Credentials>,
+    url_style: Option<UrlStyle>,
+    allow_http: Option<bool>,
+    profile: Option<String>,
+    use_environment: bool,
+    azure: Option<AzureConfig>,
+    gcs: Option<GcsConfig>,
+    buckets: HashMap<String, StorageConfig>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            endpoint: None,
+            region: None,
+            credentials: None,
+            url_style: None,
+            allow_http: None,
+            profile: None,
+            use_environment: true,
+            azure: None,
+            gcs: None,
+            buckets: HashMap::new(),
+        }
+    }
+}
+
+impl StorageConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Endpoint of an S3 compatible store, e.g. `http://localhost:9000` for MinIO.
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    pub fn region(mut self, region: &str) -> Self {
+        self.region = Some(region.to_string());
+        self
+    }
+
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Path style by default, which S3 compatible stores usually need.
+    pub fn url_style(mut self, url_style: UrlStyle) -> Self {
+        self.url_style = Some(url_style);
+        self
+    }
+
+    /// Allows plain HTTP connections, on by default for `http://` endpoints.
+    pub fn allow_http(mut self, allow_http: bool) -> Self {
+        self.allow_http = Some(allow_http);
+        self
+    }
+
+    /// Profile of the AWS config files that unset settings are loaded from.
+    pub fn profile(mut self, profile: &str) -> Self {
+        self.profile = Some(profile.to_string());
+        self
+    }
+
+    /// Whether unset settings are loaded from the AWS environment, on by default.
+    pub fn use_environment(mut self, use_environment: bool) -> Self {
+        self.use_environment = use_environment;
+        self
+    }
+
+    /// Settings of `az://` and `abfss://` tables.
+    pub fn azure(mut self, azure: AzureConfig) -> Self {
+        self.azure = Some(azure);
+        self
+    }
+
+    /// Settings of `gs://` tables.
+    pub fn gcs(mut self, gcs: GcsConfig) -> Self {
+        self.gcs = Some(gcs);
+        self
+    }
+
+    /// Settings of a single bucket or Azure container, e.g. one on another endpoint.
+    /// Settings the bucket leaves unset are taken from this config.
+    pub fn bucket(mut self, bucket: &str, config: StorageConfig) -> Self {
+        self.buckets.insert(bucket.to_string(), config);
+        self
+    }
+
+    /// The config of the bucket `uri` points to, without per-bucket overrides.
+    pub(crate) fn for_uri(&self, uri: &str) -> StorageConfig {
+        // abfss://container@account.dfs.core.windows.net/path is in the container
+        let bucket = uri
+            .split_once("://")
+            .and_then(|(_, path)| path.split(['/', '@']).next());
+        match bucket.and_then(|bucket| self.buckets.get(bucket)) {
+            Some(config) => self.merged_with(config),
+            None => self.merged_with(&StorageConfig::default()),
+        }
+    }
+
+    /// `config` with the settings it leaves unset taken from `self`.
+    fn merged_with(&self, config: &StorageConfig) -> StorageConfig {
+        StorageConfig {
+            endpoint: config.endpoint.clone().or_else(|| self.endpoint.clone()),
+            region: config.region.clone().or_else(|| self.region.clone()),
+            credentials: config
+                .credentials
+                .clone()
+                .or_else(|| self.credentials.clone()),
+            url_style: config.url_style.or(self.url_style),
+            allow_http: config.allow_http.or(self.allow_http),
+            profile: config.profile.clone().or_else(|| self.profile.clone()),
+            use_environment: self.use_environment && config.use_environment,
+            azure: config.azure.clone().or_else(|| self.azure.clone()),
+            gcs: config.gcs.clone().or_else(|| self.gcs.clone()),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Fills unset settings from the AWS environment, when that is enabled.
+    pub(crate) async fn resolve(&self) -> StorageConfig {
+        let mut config = self.clone();
+        let complete =
+            config.endpoint.is_some() && config.region.is_some() && config.credentials.is_some();
+        if !config.use_environment || complete {
+            return config;
+        }
+
+        let mut loader = aws_config::defaults(BehaviorVersion::v2024_03_28());
+        if let Some(profile) = &config.profile {
+            loader = loader.profile_name(profile);
+        }
+        let environment = loader.load().await;
+
+        if config.endpoint.is_none() {
+            config.endpoint = environment.endpoint_url().map(|url| url.to_string());
+        }
+        if config.region.is_none() {
+            config.region = environment.region().map(|region| region.to_string());
+        }
+        if config.credentials.is_none() {
+            if let Some(provider) = environment.credentials_provider() {
+                if let Ok(credentials) = provider.as_ref().provide_credentials().await {
+                    config.credentials = Some(Credentials {
+                        access_key_id: credentials.access_key_id().to_string(),
+                        secret_access_key: credentials.secret_access_key().to_string(),
+                        session_token: credentials.session_token().map(|s| s.to_string()),
+                    });
+                }
+            }
+        }
+        config
+    }
+
+    fn allows_http(&self) -> bool {
+        self.allow_http.unwrap_or_else(|| {
+            self.endpoint
+                .as_deref()
+                .is_some_and(|endpoint| endpoint.starts_with("http://"))
+        })
+    }
+
+    /// The deltalake storage options of a resolved config for `s3://` tables.
+    pub(crate) fn to_storage_options(&self) -> HashMap<String, String> {
+        let mut options = HashMap::new();
+
+        options.insert(AWS_FORCE_CREDENTIAL_LOAD.to_string(), "true".to_string());
+        options.insert(AWS_S3_ALLOW_UNSAFE_RENAME.to_string(), "true".to_string());
+
+        if let Some(endpoint) = &self.endpoint {
+            options.insert(AWS_ENDPOINT_URL.to_string(), endpoint.clone());
+        }
+        if let Some(region) = &self.region {
+            options.insert(AWS_REGION.to_string(), region.clone());
+        }
+        if let Some(credentials) = &self.credentials {
+            options.insert(
+                AWS_ACCESS_KEY_ID.to_string(),
+                credentials.access_key_id.clone(),
+            );
+            options.insert(
+                AWS_SECRET_ACCESS_KEY.to_string(),
+                credentials.secret_access_key.clone(),
+            );
+            if let Some(session_token) = &credentials.session_token {
+                options.insert(AWS_SESSION_TOKEN.to_string(), session_token.clone());
+            }
+        }
+        if let Some(profile) = &self.profile {
+            options.insert(AWS_PROFILE.to_string(), profile.clone());
+        }
+
+        let addressing_style = match self.url_style.unwrap_or_default() {
+            UrlStyle::Path => "path",
+            UrlStyle::VirtualHosted => "virtual",
+        };
+        options.insert(
+            AWS_S3_ADDRESSING_STYLE.to_string(),
+            addressing_style.to_string(),
+        );
+        options.insert(AWS_ALLOW_HTTP.to_string(), self.allows_http().to_string());
+
+        options
+    }
+
+    /// `CREATE SECRET` statement of a resolved config, limited to `scope` when given.
+    fn to_duckdb_secret(&self, name: &str, scope: Option<&str>) -> String {
+        let mut params = vec!["TYPE S3".to_string()];
+
+        if let Some(credentials) = &self.credentials {
+            params.push(format!(
+                "KEY_ID {}",
+                quote_literal(&credentials.access_key_id)
+            ));
+            params.push(format!(
+                "SECRET {}",
+                quote_literal(&credentials.secret_access_key)
+            ));
+            if let Some(session_token) = &credentials.session_token {
+                params.push(format!("SESSION_TOKEN {}", quote_literal(session_token)));
+            }
+        }
+        if let Some(region) = &self.region {
+            params.push(format!("REGION {}", quote_literal(region)));
+        }
+        if let Some(endpoint) = &self.endpoint {
+            params.push(format!(
+                "ENDPOINT {}",
+                quote_literal(endpoint_host(endpoint))
+            ));
+            params.push(format!("USE_SSL {}", !endpoint.starts_with("http://")));
+        }
+        let url_style = match self.url_style.unwrap_or_default() {
+            UrlStyle::Path => "path",
+            UrlStyle::VirtualHosted => "vhost",
+        };
+        params.push(format!("URL_STYLE '{}'", url_style));
+        if let Some(scope) = scope {
+            params.push(format!("SCOPE {}", quote_literal(scope)));
+        }
+
+        format!("CREATE OR REPLACE SECRET {} ({});", name, params.join(", "))
+    }
+
+    /// The deltalake storage options for `az://` and `abfss://` tables.
+    pub(crate) fn to_azure_storage_options(&self) -> HashMap<String, String> {
+        self.azure
+            .as_ref()
+            .map(AzureConfig::to_storage_options)
+            .unwrap_or_default()
+    }
+
+    /// The deltalake storage options for `gs://` tables.
+    pub(crate) fn to_gcs_storage_options(&self) -> HashMap<String, String> {
+        self.gcs
+            .as_ref()
+            .map(GcsConfig::to_storage_options)
+            .unwrap_or_default()
+    }
+
+    /// `CREATE SECRET` statements of the config and of each overridden bucket scoped to it,
+    /// DuckDB picks the secret with the longest matching scope. Azure and GCS secrets are
+    /// only created when they are configured.
+    pub(crate) async fn to_duckdb_secrets(&self) -> String {
+        let base = self.for_uri("");
+        let mut sql = base.resolve().await.to_duckdb_secret("duckdelta_s3", None);
+        if let Some(azure) = &base.azure {
+            sql.push_str(&azure.to_duckdb_secret("duckdelta_azure", None));
+        }
+        if let Some(gcs) = &base.gcs {
+            sql.push_str(&gcs.to_duckdb_secret("duckdelta_gcs", None));
+        }
+        let mut uses_azure = base.azure.is_some();
+
+        let mut buckets: Vec<(&String, &StorageConfig)> = self.buckets.iter().collect();
+        buckets.sort_by(|a, b| a.0.cmp(b.0));
+        for (index, (bucket, overrides)) in buckets.into_iter().enumerate() {
+            let scope = format!("s3://{}", bucket);
+            let config = self.for_uri(&scope).resolve().await;
+            sql.push_str(
+                &config.to_duckdb_secret(&format!("duckdelta_s3_{}", index), Some(&scope)),
+            );
+            if let (Some(azure), Some(_)) = (&config.azure, &overrides.azure) {
+                uses_azure = true;
+                sql.push_str(&azure.to_duckdb_secret(
+                    &format!("duckdelta_azure_{}", index),
+                    Some(&format!("az://{}", bucket)),
+                ));
+                // ADLS Gen2 URIs name the container before the account host
+                let abfss_scope = match azure.get_account_name() {
+                    Some(account_name) => {
+                        format!("abfss://{}@{}.dfs.core.windows.net", bucket, account_name)
+                    }
+                    None => format!("abfss://{}@", bucket),
+                };
+                sql.push_str(&azure.to_duckdb_secret(
+                    &format!("duckdelta_azure_{}_abfss", index),
+                    Some(&abfss_scope),
+                ));
+            }
+            if let (Some(gcs), Some(_)) = (&config.gcs, &overrides.gcs) {
+                sql.push_str(&gcs.to_duckdb_secret(
+                    &format!("duckdelta_gcs_{}", index),
+                    Some(&format!("gs://{}", bucket)),
+                ));
+            }
+        }
+
+        // Azure secrets need the azure extension, httpfs handles S3 and GCS
+        if uses_azure {
+            sql.insert_str(0, "INSTALL azure; LOAD azure;");
+        }
+        sql
+    }
+}
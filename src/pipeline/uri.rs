@@ -0,0 +1,166 @@
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Once, OnceLock};
+
+use deltalake::storage::object_store::memory::InMemory;
+use deltalake::storage::{
+    factories, limit_store_handler, url_prefix_handler, ObjectStoreFactory, ObjectStoreRef,
+    StorageOptions,
+};
+use deltalake::DeltaResult;
+use url::Url;
+
+use crate::error::Error;
+
+/// Schemes of remote delta table locations, passed on as they are.
+const REMOTE_SCHEMES: &[&str] = &["s3", "s3a", "az", "abfs", "abfss", "gs"];
+
+/// Turns a delta table location into the URI the sinks and engines work with.
+///
+/// - `s3://`, `az://`, `abfss://` and `gs://` URIs are kept as they are.
+/// - `memory://` URIs address a store shared by the whole process, see [`memory_store`].
+/// - Local paths, with or without `file://`, become absolute `file://` URIs: relative
+///   paths are resolved against the working directory, a leading `~` against the home
+///   directory and Windows paths like `C:\data` get forward slashes.
+///
+/// Trailing slashes are removed, so `./out/` and `./out` name the same table.
+pub fn normalize_uri(uri: &str) -> Result<String, Error> {
+    let uri = uri.trim();
+    if uri.is_empty() {
+        return Err(Error::InvalidUri("The table location is empty".to_string()));
+    }
+
+    if let Some(path) = uri.strip_prefix("file://") {
+        return local_uri(path);
+    }
+
+    match uri.split_once("://") {
+        Some(("memory", _)) => {
+            register_memory_store();
+            Ok(trim_trailing_slashes(uri).to_string())
+        }
+        Some((scheme, _)) if REMOTE_SCHEMES.contains(&scheme) => {
+            Ok(trim_trailing_slashes(uri).to_string())
+        }
+        // Windows paths can not contain `://`, everything else with it is a scheme
+        Some((scheme, _)) => Err(Error::InvalidUri(format!(
+            "Unsupported storage scheme {}:// of {}, expected s3://, az://, abfss://, gs://, memory://, file:// or a local path",
+            scheme, uri
+        ))),
+        None => local_uri(uri),
+    }
+}
+
+fn trim_trailing_slashes(uri: &str) -> &str {
+    let trimmed = uri.trim_end_matches('/');
+    // Keep the root of the file system and URIs without a path
+    if trimmed.is_empty() || trimmed.ends_with(':') || trimmed.ends_with("://") {
+        uri
+    } else {
+        trimmed
+    }
+}
+
+/// `C:\data`, `C:/data` or `/C:/data`, as found after `file://`.
+fn windows_path(path: &str) -> Option<String> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    let bytes = path.as_bytes();
+    if bytes.len() >= 3
+        && bytes[0].is_ascii_alphabetic()
+        && bytes[1] == b':'
+        && (bytes[2] == b'\\' || bytes[2] == b'/')
+    {
+        Some(path.replace('\\', "/"))
+    } else {
+        None
+    }
+}
+
+fn local_uri(path: &str) -> Result<String, Error> {
+    if let Some(path) = windows_path(path) {
+        return Ok(format!("file:///{}", trim_trailing_slashes(&path)));
+    }
+
+    let path = expand_home(path)?;
+    let path = if path.is_absolute() {
+        path
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let path = clean_path(&path);
+
+    let path = path
+        .to_str()
+        .ok_or_else(|| Error::InvalidUri(format!("Path {} is not valid UTF-8", path.display())))?;
+    Ok(format!("file://{}", trim_trailing_slashes(path)))
+}
+
+fn expand_home(path: &str) -> Result<PathBuf, Error> {
+    let rest = match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.trim_start_matches('/'),
+        // `~user` is not expanded
+        _ => return Ok(PathBuf::from(path)),
+    };
+
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .ok_or_else(|| {
+            Error::InvalidUri(format!(
+                "Can not expand {}, the home directory is not set",
+                path
+            ))
+        })?;
+    Ok(PathBuf::from(home).join(rest))
+}
+
+/// Resolves `.` and `..` without touching the file system, the table may not exist yet.
+fn clean_path(path: &Path) -> PathBuf {
+    let mut cleaned = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                cleaned.pop();
+            }
+            component => cleaned.push(component),
+        }
+    }
+    cleaned
+}
+
+/// The in-memory object store behind every `memory://` URI of the process.
+///
+/// deltalake creates a new empty store for each `memory://` table it opens, so a table
+/// written by one step would be gone for the next. Sharing one store makes `memory://`
+/// behave like a bucket that lives as long as the process, e.g. for tests that should not
+/// touch the disk or S3. `memory://bucket/table` and `memory:///bucket/table` are the same
+/// table. DuckDB can not scan these tables, query them with the DataFusion engine.
+pub fn memory_store() -> Arc<InMemory> {
+    static STORE: OnceLock<Arc<InMemory>> = OnceLock::new();
+    STORE.get_or_init(|| Arc::new(InMemory::new())).clone()
+}
+
+struct SharedMemoryFactory;
+
+impl ObjectStoreFactory for SharedMemoryFactory {
+    fn parse_url_opts(
+        &self,
+        url: &Url,
+        options: &StorageOptions,
+    ) -> DeltaResult<(ObjectStoreRef, deltalake::Path)> {
+        let location = format!("{}{}", url.host_str().unwrap_or_default(), url.path());
+        let prefix = deltalake::Path::from_url_path(location.trim_matches('/'))?;
+        let inner = memory_store() as ObjectStoreRef;
+        let store = limit_store_handler(url_prefix_handler(inner, prefix.clone()), options);
+        Ok((store, prefix))
+    }
+}
+
+/// Replaces the `memory://` factory of deltalake with one handing out [`memory_store`].
+fn register_memory_store() {
+    static REGISTER: Once = Once::new();
+    REGISTER.call_once(|| {
+        if let Ok(url) = Url::parse("memory://") {
+            factories().insert(url, Arc::new(SharedMemoryFactory));
+        }
+    });
+}
@@ -1,14 +1,51 @@
-use std::{fs, io, path::Path, time::Instant};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use csv::Writer;
-use deltalake::datafusion::prelude::{ParquetReadOptions, SessionContext};
+use deltalake::arrow::{
+    array::{ArrayRef, Int32Array, RecordBatch, StringArray},
+    compute::{cast, concat_batches},
+    datatypes::DataType,
+};
+use deltalake::datafusion::error::DataFusionError;
+use deltalake::datafusion::prelude::{ident, lit, ParquetReadOptions, SessionContext};
+use deltalake::parquet::arrow::ArrowWriter;
 use duckdelta::{
     error::Error,
-    pipeline::{engines, Pipeline},
+    pipeline::{
+        engines,
+        sinks::{
+            MergeSpec, OptimizeOptions, SchemaMode, StreamOptions, VacuumOptions, WriteMode,
+            WriteOptions,
+        },
+        sources::{CsvOptions, DeltaReadOptions, JsonFormat, JsonOptions, SourcesType},
+        storage::{AzureConfig, Credentials, GcsConfig, StorageConfig, UrlStyle},
+        uri::normalize_uri,
+        Pipeline,
+    },
 };
-use engines::DuckDB;
+use engines::{DataFusionEngine, DuckDB, DuckDBPool, Engine, PoolOptions, SqlParams, SqlValue};
+use tempfile::TempDir;
 use tokio::task;
 
+/// Creates a folder for a test in the working directory. It is removed when the returned
+/// `TempDir` is dropped, also when the test fails.
+fn test_folder(name: &str) -> Result<(TempDir, String), Error> {
+    let temp_dir = tempfile::Builder::new()
+        .prefix(name)
+        .tempdir_in(std::env::current_dir()?)?;
+    let folder = temp_dir.path().display().to_string();
+    Ok((temp_dir, folder))
+}
+
 /// Get a list of all CSV files in a folder and its subfolders with full paths
 fn get_csv_files(folder: &str) -> io::Result<Vec<String>> {
     let mut csv_files = Vec::new();
@@ -77,12 +114,19 @@ async fn generate_second_data(file_name_str: &str) -> Result<(), Error> {
     Ok(())
 }
 
+/// Counts the rows of the latest version of a delta table
+async fn count_delta_rows(uri: &str) -> Result<usize, Error> {
+    let table = deltalake::open_table(uri).await?;
+    let ctx = SessionContext::new();
+    Ok(ctx.read_table(Arc::new(table))?.count().await?)
+}
+
 #[tokio::test]
 async fn test_pipeline() -> Result<(), Error> {
     let folder_test = "/Users/abdulharisdjafar/Documents/private/code/duckdelta/test_pipeline";
     let file1 = format!("{}/file1.csv", folder_test);
     let local_delta_place = format!("file://{}", folder_test);
-    let s3_delta_place = format!("s3://datalake");
+    let s3_delta_place = "s3://datalake".to_string();
 
     // test in local
     fs::create_dir(folder_test)?;
@@ -94,31 +138,31 @@ async fn test_pipeline() -> Result<(), Error> {
     pipeline
         .read_csv(&file1)
         .await?
-        .write_delta(&s3_delta_place, "tb_delta")
+        .write_delta(&s3_delta_place, "tb_delta", WriteMode::Append)
         .await?;
 
     pipeline
         .execute_sql("SELECT * from delta_tb_delta")
         .await?
-        .write_delta(&s3_delta_place, "tb_from_delta")
+        .write_delta(&s3_delta_place, "tb_from_delta", WriteMode::Append)
         .await?;
 
     pipeline
         .read_csv(&file1)
         .await?
-        .write_delta(&local_delta_place, "tb_1")
+        .write_delta(&local_delta_place, "tb_1", WriteMode::Append)
         .await?;
 
     pipeline
         .execute_sql("SELECT * from delta_tb_1")
         .await?
-        .write_delta(&local_delta_place, "tb_2")
+        .write_delta(&local_delta_place, "tb_2", WriteMode::Append)
         .await?;
 
     pipeline
         .read_csv(&file1)
         .await?
-        .write_delta(&s3_delta_place, "tb_1")
+        .write_delta(&s3_delta_place, "tb_1", WriteMode::Append)
         .await?;
 
     let ctx = SessionContext::new();
@@ -149,7 +193,7 @@ async fn test_merge_update_pipeline() -> Result<(), Error> {
         "/Users/abdulharisdjafar/Documents/private/code/duckdelta/test_merge_update_pipeline";
     let file1 = format!("{}/file1.csv", folder_test);
     let file2 = format!("{}/file2.csv", folder_test);
-    let s3_delta_place = format!("s3://datalake");
+    let s3_delta_place = "s3://datalake".to_string();
 
     // test in local
     fs::create_dir(folder_test)?;
@@ -162,13 +206,13 @@ async fn test_merge_update_pipeline() -> Result<(), Error> {
     pipeline
         .read_csv(&file1)
         .await?
-        .write_delta(&s3_delta_place, "tb_delta_merge")
+        .write_delta(&s3_delta_place, "tb_delta_merge", WriteMode::Append)
         .await?;
 
     pipeline
         .read_csv(&file1)
         .await?
-        .write_delta(&s3_delta_place, "tb_delta_merge_2")
+        .write_delta(&s3_delta_place, "tb_delta_merge_2", WriteMode::Append)
         .await?;
 
     pipeline
@@ -189,9 +233,11 @@ async fn test_parallel_pipeline() -> Result<(), Error> {
     let mut tasks = Vec::new();
 
     let test_start_time = Instant::now();
+    let pool = DuckDBPool::new(PoolOptions::new().size(4)).await?;
 
     for file in list_files {
         let file_clone = file.clone();
+        let pool = pool.clone();
         tasks.push(task::spawn(async move {
             let task_start_time = Instant::now();
 
@@ -200,13 +246,13 @@ async fn test_parallel_pipeline() -> Result<(), Error> {
             let table_path = table_path_arr.first().unwrap();
             println!("Start processing: {}", table_path);
 
-            let duck_engine = DuckDB::new().await.unwrap();
+            let duck_engine = pool.engine().await.unwrap();
             let mut pipeline = Pipeline::new(duck_engine).await.unwrap();
             pipeline
                 .read_csv(&file_clone)
                 .await
                 .unwrap()
-                .write_delta("s3://datalake", table_path)
+                .write_delta("s3://datalake", table_path, WriteMode::Append)
                 .await
                 .unwrap();
 
@@ -227,3 +273,1683 @@ async fn test_parallel_pipeline() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Writes `batch` as a single parquet file, creating the parent folders.
+fn write_parquet_file(path: &str, batch: &RecordBatch) -> Result<(), Error> {
+    fs::create_dir_all(Path::new(path).parent().unwrap())?;
+    let mut writer = ArrowWriter::try_new(fs::File::create(path)?, batch.schema(), None)
+        .map_err(DataFusionError::from)?;
+    writer.write(batch).map_err(DataFusionError::from)?;
+    writer.close().map_err(DataFusionError::from)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_parquet_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_read_parquet_pipeline")?;
+    let events = format!("{}/events", folder_test);
+
+    // the second partition has a column the first one does not have
+    let first = RecordBatch::try_from_iter(vec![
+        ("id", Arc::new(Int32Array::from(vec![1, 2])) as ArrayRef),
+        (
+            "name",
+            Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+        ),
+    ])?;
+    let second = RecordBatch::try_from_iter(vec![
+        ("id", Arc::new(Int32Array::from(vec![3])) as ArrayRef),
+        ("name", Arc::new(StringArray::from(vec!["c"])) as ArrayRef),
+        (
+            "city",
+            Arc::new(StringArray::from(vec!["Hanoi"])) as ArrayRef,
+        ),
+    ])?;
+    // files that are not part of the data, with a schema of their own
+    let stray = RecordBatch::try_from_iter(vec![(
+        "stray",
+        Arc::new(Int32Array::from(vec![0])) as ArrayRef,
+    )])?;
+
+    write_parquet_file(
+        &format!("{}/date=2024-01-01/part-0.parquet", events),
+        &first,
+    )?;
+    write_parquet_file(
+        &format!("{}/date=2024-01-02/part-0.parquet", events),
+        &second,
+    )?;
+    write_parquet_file(
+        &format!("{}/_delta_log/00.checkpoint.parquet", events),
+        &stray,
+    )?;
+    write_parquet_file(&format!("{}/_temporary/part-1.parquet", events), &stray)?;
+    write_parquet_file(
+        &format!("{}/date=2024-01-02/.part-1.parquet", events),
+        &stray,
+    )?;
+
+    let duck_engine = DuckDB::new().await?;
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_parquet(&events)
+        .await?
+        .register_as("events")
+        .await?
+        .execute_sql("SELECT * FROM events ORDER BY id")
+        .await?;
+
+    let batches = pipeline
+        .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+        .unwrap();
+    let batch = concat_batches(&batches[0].schema(), batches)?;
+    let schema = batch.schema();
+    let mut columns: Vec<&str> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    columns.sort();
+    assert_eq!(columns, vec!["city", "date", "id", "name"]);
+    assert_eq!(batch.num_rows(), 3);
+
+    let dates = cast(batch.column_by_name("date").unwrap(), &DataType::Utf8)?;
+    let dates = dates.as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(
+        dates.iter().collect::<Vec<_>>(),
+        vec![Some("2024-01-01"), Some("2024-01-01"), Some("2024-01-02")]
+    );
+    let cities = cast(batch.column_by_name("city").unwrap(), &DataType::Utf8)?;
+    let cities = cities.as_any().downcast_ref::<StringArray>().unwrap();
+    assert_eq!(
+        cities.iter().collect::<Vec<_>>(),
+        vec![None, None, Some("Hanoi")]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_json_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_read_json_pipeline")?;
+    let ndjson_file = format!("{}/file1.ndjson", folder_test);
+    let array_file = format!("{}/file2.json", folder_test);
+
+    fs::write(
+        &ndjson_file,
+        "{\"Name\": \"Alice\", \"Age\": 30, \"Address\": {\"City\": \"New York\"}}\n\
+         {\"Name\": \"Bob\", \"Age\": 25, \"Address\": {\"City\": \"San Francisco\"}}\n",
+    )?;
+    fs::write(
+        &array_file,
+        "[{\"Name\": \"Charlie\", \"Age\": 35}, {\"Name\": \"Dave\", \"Age\": 40}]",
+    )?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_json(&ndjson_file)
+        .await?
+        .write_delta(
+            &format!("file://{}", folder_test),
+            "tb_ndjson",
+            WriteMode::Append,
+        )
+        .await?;
+
+    pipeline
+        .read_json_with_options(
+            &array_file,
+            &JsonOptions::new()
+                .format(JsonFormat::Array)
+                .column("Name", "VARCHAR")
+                .column("Age", "BIGINT"),
+        )
+        .await?
+        .write_delta(
+            &format!("file://{}", folder_test),
+            "tb_json_array",
+            WriteMode::Append,
+        )
+        .await?;
+
+    let ctx = SessionContext::new();
+    let df = ctx
+        .read_parquet(
+            format!("{}/tb_json_array/*.parquet", folder_test),
+            ParquetReadOptions::new(),
+        )
+        .await?;
+    assert_eq!(df.count().await?, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_delta_time_travel_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_read_delta_time_travel_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+    let table_uri = format!("{}/tb_history", local_delta_place);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    // version 0 and version 1 of the same table
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_history", WriteMode::Append)
+        .await?;
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_history", WriteMode::Append)
+        .await?;
+
+    pipeline
+        .read_delta_with_options(&table_uri, &DeltaReadOptions::new().as_of_version(0))
+        .await?
+        .write_delta(&local_delta_place, "tb_version_0", WriteMode::Append)
+        .await?;
+
+    pipeline
+        .read_delta(&table_uri)
+        .await?
+        .write_delta(&local_delta_place, "tb_latest", WriteMode::Append)
+        .await?;
+
+    let ctx = SessionContext::new();
+    let df_version_0 = ctx
+        .read_parquet(
+            format!("{}/tb_version_0/*.parquet", folder_test),
+            ParquetReadOptions::new(),
+        )
+        .await?;
+    let df_latest = ctx
+        .read_parquet(
+            format!("{}/tb_latest/*.parquet", folder_test),
+            ParquetReadOptions::new(),
+        )
+        .await?;
+    assert_eq!(df_version_0.count().await?, 3);
+    assert_eq!(df_latest.count().await?, 6);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_csv_with_options_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_read_csv_with_options_pipeline")?;
+    let vendor_file = format!("{}/vendor.csv", folder_test);
+
+    // semicolon delimited latin-1 file with european dates and one broken row
+    fs::write(
+        &vendor_file,
+        b"Name;Birthday;City\nJos\xe9;31.12.1990;M\xfcnchen\nAnna;01.02.1985;NA\nBroken;not a date;Wien\n",
+    )?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    let options = CsvOptions::new()
+        .delimiter(";")
+        .header(true)
+        .encoding("latin-1")
+        .date_format("%d.%m.%Y")
+        .column_type("Birthday", "DATE")
+        .null_string("NA")
+        .rejects_table("vendor_rejects");
+
+    pipeline
+        .read_csv_with_options(&vendor_file, &options)
+        .await?
+        .write_delta(
+            &format!("file://{}", folder_test),
+            "tb_vendor",
+            WriteMode::Append,
+        )
+        .await?;
+
+    let rejected_rows: usize = pipeline
+        .rejected_rows()
+        .unwrap()
+        .iter()
+        .map(|batch| batch.num_rows())
+        .sum();
+    assert_eq!(rejected_rows, 1);
+
+    let ctx = SessionContext::new();
+    let df = ctx
+        .read_parquet(
+            format!("{}/tb_vendor/*.parquet", folder_test),
+            ParquetReadOptions::new(),
+        )
+        .await?;
+    assert_eq!(df.clone().count().await?, 2);
+    assert_eq!(
+        df.filter(ident("City").eq(lit("München")))?.count().await?,
+        1
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_read_csv_utf16_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_read_csv_utf16_pipeline")?;
+    let utf16_file = format!("{}/cities.csv", folder_test);
+
+    // big endian with a byte order mark, large enough to be transcoded in several chunks
+    let mut content = String::from("Id,City\n");
+    for id in 0..5000 {
+        content.push_str(&format!("{},München 🏔\n", id));
+    }
+    let mut bytes = vec![0xfe, 0xff];
+    bytes.extend(content.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+    fs::write(&utf16_file, bytes)?;
+
+    let duck_engine = DuckDB::new().await?;
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv_with_options(&utf16_file, &CsvOptions::new().encoding("utf-16"))
+        .await?
+        .write_delta(
+            &format!("file://{}", folder_test),
+            "tb_cities",
+            WriteMode::Append,
+        )
+        .await?;
+
+    let ctx = SessionContext::new();
+    let df = ctx
+        .read_parquet(
+            format!("{}/tb_cities/*.parquet", folder_test),
+            ParquetReadOptions::new(),
+        )
+        .await?;
+    assert_eq!(
+        df.filter(ident("City").eq(lit("München 🏔")))?
+            .count()
+            .await?,
+        5000
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_write_modes_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_write_modes_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+    let table_uri = format!("{}/tb_modes", local_delta_place);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+    pipeline.read_csv(&file1).await?;
+
+    pipeline
+        .write_delta(&local_delta_place, "tb_modes", WriteMode::Append)
+        .await?;
+    pipeline
+        .write_delta(&local_delta_place, "tb_modes", WriteMode::Append)
+        .await?;
+    assert_eq!(count_delta_rows(&table_uri).await?, 6);
+
+    pipeline
+        .write_delta(&local_delta_place, "tb_modes", WriteMode::Overwrite)
+        .await?;
+    assert_eq!(count_delta_rows(&table_uri).await?, 3);
+
+    pipeline
+        .write_delta(&local_delta_place, "tb_modes", WriteMode::Ignore)
+        .await?;
+    assert_eq!(count_delta_rows(&table_uri).await?, 3);
+
+    assert!(pipeline
+        .write_delta(&local_delta_place, "tb_modes", WriteMode::ErrorIfExists)
+        .await
+        .is_err());
+
+    // re-running the load of one city only replaces that city
+    pipeline
+        .execute_sql("SELECT * FROM delta_tb_modes WHERE City = 'Chicago'")
+        .await?;
+    for _ in 0..2 {
+        pipeline
+            .write_delta(
+                &local_delta_place,
+                "tb_modes",
+                WriteMode::ReplaceWhere("\"City\" = 'Chicago'".to_string()),
+            )
+            .await?;
+    }
+    assert_eq!(count_delta_rows(&table_uri).await?, 3);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_partitioned_write_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_partitioned_write_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta_with_options(
+            &local_delta_place,
+            "tb_partitioned",
+            &WriteOptions::new(WriteMode::Append).partition_by(&["City"]),
+        )
+        .await?;
+
+    assert!(Path::new(&format!("{}/tb_partitioned/City=Chicago", folder_test)).is_dir());
+
+    // appends without partition columns follow the table's partitioning
+    pipeline
+        .write_delta(&local_delta_place, "tb_partitioned", WriteMode::Append)
+        .await?;
+
+    let result = pipeline
+        .write_delta_with_options(
+            &local_delta_place,
+            "tb_partitioned",
+            &WriteOptions::new(WriteMode::Append).partition_by(&["Name"]),
+        )
+        .await;
+    assert!(matches!(result, Err(Error::PartitionMismatch(_))));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_merge_spec_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_merge_spec_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let file2 = format!("{}/file2.csv", folder_test);
+    let file3 = format!("{}/file3.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    generate_second_data(&file2).await?;
+    let mut writer = Writer::from_path(&file3)?;
+    for row in [
+        ["Name", "Age", "City"],
+        ["Alice", "31", "New York"],
+        ["Dave", "50", "Boston"],
+    ] {
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_merge_spec", WriteMode::Append)
+        .await?;
+
+    let metrics = pipeline
+        .read_csv(&file2)
+        .await?
+        .merge_update(&local_delta_place, "tb_merge_spec", "Name", &["Age"])
+        .await?;
+    assert_eq!(metrics.num_target_rows_updated, 3);
+    assert_eq!(metrics.num_target_rows_inserted, 0);
+
+    let spec = MergeSpec::new(&["Name"])
+        .when_matched_update_all(Some("source.\"Age\" <> target.\"Age\""))
+        .when_not_matched_insert_all(None)
+        .when_not_matched_by_source_delete(None);
+    let metrics = pipeline
+        .read_csv(&file3)
+        .await?
+        .merge(&local_delta_place, "tb_merge_spec", &spec)
+        .await?;
+    assert_eq!(metrics.num_target_rows_updated, 1);
+    assert_eq!(metrics.num_target_rows_inserted, 1);
+    assert_eq!(metrics.num_target_rows_deleted, 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_merge_scd2_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_merge_scd2_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let file2 = format!("{}/file2.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    generate_second_data(&file2).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    let metrics = pipeline
+        .read_csv(&file1)
+        .await?
+        .merge_scd2(
+            &local_delta_place,
+            "dim_people",
+            &["Name"],
+            &["Age", "City"],
+        )
+        .await?;
+    assert_eq!(metrics.num_target_rows_inserted, 3);
+
+    // unchanged rows are left alone
+    let metrics = pipeline
+        .merge_scd2(
+            &local_delta_place,
+            "dim_people",
+            &["Name"],
+            &["Age", "City"],
+        )
+        .await?;
+    assert_eq!(metrics.num_target_rows_updated, 0);
+    assert_eq!(metrics.num_target_rows_inserted, 0);
+
+    // every age changed: three versions are closed and three new ones inserted
+    let metrics = pipeline
+        .read_csv(&file2)
+        .await?
+        .merge_scd2(
+            &local_delta_place,
+            "dim_people",
+            &["Name"],
+            &["Age", "City"],
+        )
+        .await?;
+    assert_eq!(metrics.num_target_rows_updated, 3);
+    assert_eq!(metrics.num_target_rows_inserted, 3);
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/dim_people", local_delta_place)).await?,
+        6
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delete_update_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_delete_update_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_people", WriteMode::Append)
+        .await?;
+
+    let updated_rows = pipeline
+        .update(
+            &local_delta_place,
+            "tb_people",
+            "\"Age\" > 28",
+            &[("City", "'Boston'")],
+        )
+        .await?;
+    assert_eq!(updated_rows, 2);
+
+    let deleted_rows = pipeline
+        .delete(&local_delta_place, "tb_people", "\"Name\" = 'Bob'")
+        .await?;
+    assert_eq!(deleted_rows, 1);
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_people", local_delta_place)).await?,
+        2
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_maintenance_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_maintenance_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+    pipeline.read_csv(&file1).await?;
+    for _ in 0..3 {
+        pipeline
+            .write_delta(&local_delta_place, "tb_small_files", WriteMode::Append)
+            .await?;
+    }
+
+    let metrics = pipeline
+        .optimize(
+            &local_delta_place,
+            "tb_small_files",
+            &OptimizeOptions::new(),
+        )
+        .await?;
+    assert_eq!(metrics.num_files_removed, 3);
+    assert_eq!(metrics.num_files_added, 1);
+
+    let metrics = pipeline
+        .optimize(
+            &local_delta_place,
+            "tb_small_files",
+            &OptimizeOptions::new().z_order(&["Age"]),
+        )
+        .await?;
+    assert_eq!(metrics.num_files_added, 1);
+
+    let options = VacuumOptions::new()
+        .retention_hours(0)
+        .enforce_retention_duration(false)
+        .dry_run(true);
+    let metrics = pipeline
+        .vacuum(&local_delta_place, "tb_small_files", &options)
+        .await?;
+    assert_eq!(metrics.files_deleted.len(), 4);
+
+    // a retention beyond the range of a duration is rejected instead of wrapping around
+    let options = VacuumOptions::new().retention_hours(u64::MAX).dry_run(true);
+    assert!(matches!(
+        pipeline
+            .vacuum(&local_delta_place, "tb_small_files", &options)
+            .await,
+        Err(Error::InvalidConfig(_))
+    ));
+    let options = VacuumOptions::new()
+        .retention_hours(i64::MAX as u64)
+        .dry_run(true);
+    assert!(matches!(
+        pipeline
+            .vacuum(&local_delta_place, "tb_small_files", &options)
+            .await,
+        Err(Error::InvalidConfig(_))
+    ));
+
+    let version = pipeline
+        .checkpoint(&local_delta_place, "tb_small_files")
+        .await?;
+    assert!(Path::new(&format!(
+        "{}/tb_small_files/_delta_log/{:020}.checkpoint.parquet",
+        folder_test, version
+    ))
+    .is_file());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_schema_mode_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_schema_mode_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let file2 = format!("{}/file2.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let mut writer = Writer::from_path(&file2)?;
+    for row in [
+        ["Name", "Age", "Country"],
+        ["Dave", "50", "USA"],
+        ["Eve", "41", "Canada"],
+    ] {
+        writer.write_record(row)?;
+    }
+    writer.flush()?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_schema", WriteMode::Append)
+        .await?;
+
+    let result = pipeline
+        .read_csv(&file2)
+        .await?
+        .write_delta(&local_delta_place, "tb_schema", WriteMode::Append)
+        .await;
+    match result {
+        Err(Error::SchemaMismatch(message)) => {
+            assert!(message.contains("added columns: Country"));
+            assert!(message.contains("missing columns: City"));
+        }
+        _ => panic!("expected a schema mismatch"),
+    }
+
+    pipeline
+        .write_delta_with_options(
+            &local_delta_place,
+            "tb_schema",
+            &WriteOptions::new(WriteMode::Append).schema_mode(SchemaMode::Merge),
+        )
+        .await?;
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_schema", local_delta_place)).await?,
+        5
+    );
+
+    let result = pipeline
+        .write_delta_with_options(
+            &local_delta_place,
+            "tb_schema",
+            &WriteOptions::new(WriteMode::Append).schema_mode(SchemaMode::OverwriteSchema),
+        )
+        .await;
+    assert!(matches!(result, Err(Error::SchemaMismatch(_))));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sql_rewrite_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_sql_rewrite_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_sql", WriteMode::Append)
+        .await?;
+
+    // the table name inside the string literal must stay as it is
+    pipeline
+        .execute_sql(
+            "WITH people AS (SELECT * FROM delta_tb_sql) \
+             SELECT 'delta_tb_sql' AS source_name, delta_tb_sql.Name FROM delta_tb_sql \
+             WHERE Age IN (SELECT Age FROM people) \
+             UNION ALL \
+             SELECT 'subquery', p.Name FROM (SELECT * FROM delta_tb_sql) p",
+        )
+        .await?
+        .write_delta(&local_delta_place, "tb_sql_result", WriteMode::Append)
+        .await?;
+
+    pipeline
+        .execute_sql(
+            "SELECT source_name, count(*) AS n FROM delta_tb_sql_result \
+             WHERE source_name = 'delta_tb_sql' GROUP BY source_name",
+        )
+        .await?
+        .write_delta(&local_delta_place, "tb_sql_count", WriteMode::Append)
+        .await?;
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_sql_result", local_delta_place)).await?,
+        6
+    );
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_sql_count", local_delta_place)).await?,
+        1
+    );
+
+    let row_count = |pipeline: &Pipeline<DuckDB>| {
+        pipeline
+            .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+            .map(|batches| batches.iter().map(|batch| batch.num_rows()).sum::<usize>())
+    };
+
+    // a CTE named like the mapped table reads the delta table in its own body
+    pipeline
+        .execute_sql(
+            "WITH delta_tb_sql AS (SELECT * FROM delta_tb_sql WHERE Age >= 30) \
+             SELECT * FROM delta_tb_sql",
+        )
+        .await?;
+    assert_eq!(row_count(&pipeline), Some(2));
+
+    // and only hides it in the query it is defined in
+    pipeline
+        .execute_sql(
+            "SELECT * FROM delta_tb_sql \
+             WHERE Age > (WITH delta_tb_sql AS (SELECT 0 AS Age) SELECT max(Age) FROM delta_tb_sql)",
+        )
+        .await?;
+    assert_eq!(row_count(&pipeline), Some(3));
+
+    // unquoted names are matched ignoring case, so names differing by case can not both be mapped
+    let result = pipeline
+        .register_delta_table(&format!("{}/tb_sql", local_delta_place), "DELTA_TB_SQL")
+        .await;
+    assert!(matches!(result, Err(Error::TableExists(_))));
+
+    // writes to mapped delta tables are rejected instead of running against delta_scan
+    for statement in [
+        "INSERT INTO delta_tb_sql SELECT * FROM delta_tb_sql",
+        "UPDATE delta_tb_sql SET Age = Age + 1",
+        "DELETE FROM delta_tb_sql WHERE Age > 30",
+    ] {
+        let result = pipeline.execute_sql(statement).await;
+        assert!(matches!(result, Err(Error::ReadOnlyTable(_))));
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_execute_script_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_execute_script_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let script = format!("{}/script.sql", folder_test);
+    let failing_script = format!("{}/failing_script.sql", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_script", WriteMode::Append)
+        .await?;
+
+    fs::write(
+        &script,
+        "SET threads = 2;\n\
+         CREATE TEMP TABLE adults AS SELECT * FROM delta_tb_script WHERE Age >= 30;\n\
+         CREATE VIEW adult_names AS SELECT Name FROM adults;\n\
+         SELECT * FROM adult_names;\n",
+    )?;
+    pipeline
+        .execute_script(&script)
+        .await?
+        .write_delta(&local_delta_place, "tb_script_result", WriteMode::Append)
+        .await?;
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_script_result", local_delta_place)).await?,
+        2
+    );
+
+    fs::write(&failing_script, "SELECT 1;\nSELECT * FROM missing_table;\n")?;
+    let result = pipeline.execute_script(&failing_script).await;
+    assert!(matches!(result, Err(Error::Script { index: 1, .. })));
+
+    // parse errors point at the statement as well, nothing runs before them
+    fs::write(&failing_script, "SELECT 1;\nSELEC 2;\nSELECT 'a;b';\n")?;
+    let result = pipeline.execute_script(&failing_script).await;
+    assert!(
+        matches!(result, Err(Error::Script { index: 1, ref statement, .. }) if statement == "SELEC 2")
+    );
+
+    // a single statement is still required by execute_sql
+    let result = pipeline.execute_sql("SELECT 1; SELECT 2").await;
+    assert!(result.is_err());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sql_params_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_sql_params_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_params", WriteMode::Append)
+        .await?;
+
+    pipeline
+        .execute_sql_with_params(
+            "SELECT * FROM delta_tb_params WHERE Age > ? AND City <> ?",
+            &SqlParams::positional(vec![26.into(), "O'Hare".into()]),
+        )
+        .await?
+        .write_delta(
+            &local_delta_place,
+            "tb_params_positional",
+            WriteMode::Append,
+        )
+        .await?;
+
+    // a named parameter can be used more than once
+    pipeline
+        .execute_sql_with_params(
+            "SELECT * FROM delta_tb_params WHERE City = $city OR Name = $city",
+            &SqlParams::named(&[("city", "Chicago".into())]),
+        )
+        .await?
+        .write_delta(&local_delta_place, "tb_params_named", WriteMode::Append)
+        .await?;
+
+    let vars = HashMap::from([("min_age", SqlValue::Int(30)), ("city", "New York".into())]);
+    pipeline
+        .execute_sql_template(
+            "SELECT * FROM delta_tb_params WHERE Age >= {{ min_age }} AND City = {{city}}",
+            &vars,
+        )
+        .await?
+        .write_delta(&local_delta_place, "tb_params_template", WriteMode::Append)
+        .await?;
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_params_positional", local_delta_place)).await?,
+        2
+    );
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_params_named", local_delta_place)).await?,
+        1
+    );
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_params_template", local_delta_place)).await?,
+        1
+    );
+
+    let result = pipeline
+        .execute_sql_with_params(
+            "SELECT * FROM delta_tb_params WHERE City = $missing",
+            &SqlParams::named(&[("city", "Chicago".into())]),
+        )
+        .await;
+    assert!(matches!(result, Err(Error::InvalidParameter(_))));
+
+    let result = pipeline
+        .execute_sql_template("SELECT {{ 1; DROP TABLE x }}", &vars)
+        .await;
+    assert!(matches!(result, Err(Error::InvalidParameter(_))));
+
+    // the value is quoted already, a variable inside quotes would be quoted twice
+    let result = engines::render_template("SELECT * FROM t WHERE City = '{{ city }}'", &vars);
+    assert!(matches!(result, Err(Error::InvalidParameter(_))));
+    let result = engines::render_template(r#"SELECT 1 AS "{{ city }}""#, &vars);
+    assert!(matches!(result, Err(Error::InvalidParameter(_))));
+
+    // variables in comments and braces in string literals are left as they are
+    assert_eq!(
+        engines::render_template(
+            "SELECT '{{ not a variable }}', 'it''s' -- {{ missing }}\n\
+             /* {{ missing }} /* nested */ {{ missing }} */ FROM t WHERE Age >= {{min_age}}",
+            &vars,
+        )?,
+        "SELECT '{{ not a variable }}', 'it''s' -- {{ missing }}\n\
+         /* {{ missing }} /* nested */ {{ missing }} */ FROM t WHERE Age >= 30"
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_register_as_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_register_as_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .register_as("raw")
+        .await?
+        .execute_sql("SELECT Name, Age + 1 AS Age FROM raw WHERE Age > 26")
+        .await?
+        .write_delta(&local_delta_place, "tb_registered", WriteMode::Append)
+        .await?;
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_registered", local_delta_place)).await?,
+        2
+    );
+
+    // registering again replaces the table
+    pipeline
+        .register_as("raw")
+        .await?
+        .execute_sql("SELECT * FROM raw")
+        .await?
+        .write_delta(&local_delta_place, "tb_registered_again", WriteMode::Append)
+        .await?;
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_registered_again", local_delta_place)).await?,
+        2
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_named_datasets_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_named_datasets_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let file2 = format!("{}/file2.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    generate_second_data(&file2).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    pipeline
+        .dataset("people")
+        .read_csv(&file1)
+        .await?
+        .register_as("people")
+        .await?;
+    pipeline
+        .dataset("children")
+        .read_csv(&file2)
+        .await?
+        .register_as("children")
+        .await?;
+
+    pipeline
+        .dataset("joined")
+        .execute_sql(
+            "SELECT p.Name, p.Age AS parent_age, c.Age AS child_age \
+             FROM people p JOIN children c ON p.Name = c.Name",
+        )
+        .await?
+        .write_delta(&local_delta_place, "tb_joined", WriteMode::Append)
+        .await?;
+
+    // the other datasets are still there and can be written without selecting them
+    pipeline
+        .write_dataset(
+            "people",
+            &local_delta_place,
+            "tb_people",
+            &WriteOptions::new(WriteMode::Append),
+        )
+        .await?;
+
+    // the query result still replaces the active dataset
+    pipeline.execute_sql("SELECT 1 AS one").await?;
+    assert_eq!(
+        pipeline
+            .get_dataset("joined")
+            .map(|batches| batches.iter().map(|batch| batch.num_rows()).sum::<usize>()),
+        Some(1)
+    );
+
+    assert_eq!(
+        pipeline.dataset_names(),
+        vec!["children", "joined", "people"]
+    );
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_people", local_delta_place)).await?,
+        3
+    );
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_joined", local_delta_place)).await?,
+        3
+    );
+
+    let result = pipeline
+        .dataset(duckdelta::pipeline::DEFAULT_DATASET)
+        .write_delta(&local_delta_place, "tb_default", WriteMode::Append)
+        .await;
+    assert!(matches!(result, Err(Error::UnknownDataset(_))));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_to_delta_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_stream_to_delta_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let duck_engine = DuckDB::new().await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    let csv_options = CsvOptions::default();
+    let metrics = pipeline
+        .stream_source_to_delta(
+            SourcesType::Csv(&file1, &csv_options),
+            &local_delta_place,
+            "tb_streamed",
+            &StreamOptions::new().buffered_batches(1),
+        )
+        .await?;
+    assert_eq!(metrics.num_rows, 3);
+    assert_eq!(metrics.num_files, 1);
+    assert_eq!(metrics.version, Some(0));
+
+    // small target files roll the output into several files of a single commit
+    let metrics = pipeline
+        .stream_sql_to_delta(
+            "SELECT * FROM range(100000) t(id)",
+            &local_delta_place,
+            "tb_streamed_range",
+            &StreamOptions::new().target_file_size(64 * 1024),
+        )
+        .await?;
+    assert_eq!(metrics.num_rows, 100000);
+    assert!(metrics.num_files > 1);
+    assert_eq!(metrics.version, Some(0));
+
+    // the query runs on the connection of the engine and sees its temporary tables
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .register_as("people")
+        .await?;
+    let metrics = pipeline
+        .stream_sql_to_delta(
+            "SELECT * FROM people",
+            &local_delta_place,
+            "tb_streamed_people",
+            &StreamOptions::new(),
+        )
+        .await?;
+    assert_eq!(metrics.num_rows, 3);
+
+    // the query reads the table written by the first stream through its mapping
+    let metrics = pipeline
+        .stream_sql_to_delta(
+            "SELECT * FROM delta_tb_streamed",
+            &local_delta_place,
+            "tb_streamed",
+            &StreamOptions::new(),
+        )
+        .await?;
+    assert_eq!(metrics.version, Some(1));
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_streamed", local_delta_place)).await?,
+        6
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_datafusion_engine_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_datafusion_engine_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+    let datafusion_engine = DataFusionEngine::new().await?;
+
+    let mut pipeline = Pipeline::new(datafusion_engine).await?;
+
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_1", WriteMode::Append)
+        .await?;
+
+    pipeline
+        .execute_sql("SELECT \"Name\", \"Age\" FROM delta_tb_1 WHERE \"Age\" > 26")
+        .await?
+        .write_delta(&local_delta_place, "tb_2", WriteMode::Append)
+        .await?;
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_2", local_delta_place)).await?,
+        2
+    );
+
+    // the mapped table is opened again, so the query sees the second append
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_1", WriteMode::Append)
+        .await?;
+
+    pipeline
+        .execute_sql_with_params(
+            "SELECT * FROM delta_tb_1 WHERE \"City\" = $city",
+            &SqlParams::named(&[("city", "Chicago".into())]),
+        )
+        .await?
+        .register_as("chicago")
+        .await?
+        .execute_sql("SELECT * FROM chicago")
+        .await?
+        .write_delta(&local_delta_place, "tb_chicago", WriteMode::Append)
+        .await?;
+
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_chicago", local_delta_place)).await?,
+        2
+    );
+
+    // mixed case names are normalized like DataFusion normalizes unquoted identifiers
+    pipeline
+        .register_delta_table(&format!("{}/tb_1", local_delta_place), "People")
+        .await?
+        .write_delta(&local_delta_place, "tb_Mixed", WriteMode::Append)
+        .await?;
+    for query in [
+        "SELECT * FROM People",
+        "SELECT * FROM PEOPLE",
+        "SELECT * FROM delta_tb_Mixed",
+    ] {
+        pipeline.execute_sql(query).await?;
+        assert!(
+            pipeline
+                .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+                .unwrap()
+                .iter()
+                .map(|batch| batch.num_rows())
+                .sum::<usize>()
+                > 0
+        );
+    }
+    assert!(pipeline
+        .execute_sql("SELECT * FROM \"People\"")
+        .await
+        .is_err());
+    assert_eq!(pipeline.describe_table("People").await?.name, "people");
+    pipeline.unregister_table("PEOPLE").await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_engine_runs_off_runtime_pipeline() -> Result<(), Error> {
+    let duck_engine = DuckDB::new().await?;
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+
+    // the test runtime has a single thread, the ticker only advances while the query
+    // runs on the blocking pool
+    let ticks = Arc::new(AtomicUsize::new(0));
+    let ticker = {
+        let ticks = ticks.clone();
+        task::spawn(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                ticks.fetch_add(1, Ordering::Relaxed);
+            }
+        })
+    };
+
+    pipeline
+        .execute_sql("SELECT sum(i) AS total FROM range(300000000) t(i)")
+        .await?;
+    ticker.abort();
+    assert!(ticks.load(Ordering::Relaxed) > 0);
+
+    // dropping the future stops collecting the result, the connection is released and
+    // the engine answers the next query without waiting for the whole result
+    let result = tokio::time::timeout(
+        Duration::from_millis(1),
+        pipeline.execute_sql("SELECT * FROM range(10000000) t(i)"),
+    )
+    .await;
+    assert!(result.is_err());
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        pipeline.execute_sql("SELECT 42 AS answer"),
+    )
+    .await;
+    assert!(matches!(result, Ok(Ok(_))));
+    assert_eq!(
+        pipeline
+            .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+            .map(|batches| batches.iter().map(|batch| batch.num_rows()).sum::<usize>()),
+        Some(1)
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connection_pool_pipeline() -> Result<(), Error> {
+    assert!(matches!(
+        DuckDBPool::new(PoolOptions::new().size(0)).await,
+        Err(Error::InvalidConfig(_))
+    ));
+
+    let pool = DuckDBPool::new(PoolOptions::new().size(2).threads(2).memory_limit("1GB")).await?;
+
+    let mut first = Pipeline::new(pool.engine().await?).await?;
+    let mut second = Pipeline::new(pool.engine().await?).await?;
+    assert_eq!(pool.available(), 0);
+
+    // the limits apply to every engine of the pool
+    second
+        .execute_sql("SELECT current_setting('threads') AS threads")
+        .await?;
+    let threads = second
+        .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+        .unwrap()[0]
+        .column(0)
+        .as_any()
+        .downcast_ref::<deltalake::arrow::array::Int64Array>()
+        .unwrap()
+        .value(0);
+    assert_eq!(threads, 2);
+
+    // tables are shared between the engines, temporary tables are not
+    first
+        .execute_sql("CREATE TABLE shared AS SELECT * FROM range(5) t(id)")
+        .await?;
+    first
+        .execute_sql("CREATE TEMP TABLE private AS SELECT * FROM range(5) t(id)")
+        .await?;
+    second.execute_sql("SELECT * FROM shared").await?;
+    assert_eq!(
+        second
+            .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+            .map(|batches| batches.iter().map(|batch| batch.num_rows()).sum::<usize>()),
+        Some(5)
+    );
+    assert!(second.execute_sql("SELECT * FROM private").await.is_err());
+
+    // a third engine waits until one is returned
+    assert!(
+        tokio::time::timeout(Duration::from_millis(50), pool.engine())
+            .await
+            .is_err()
+    );
+    drop(first);
+    let mut third = Pipeline::new(pool.engine().await?).await?;
+    third.execute_sql("SELECT * FROM shared").await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_persistent_database_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_persistent_database_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let database = format!("{}/catalog.duckdb", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+
+    {
+        let mut pipeline = Pipeline::new(DuckDB::open(&database).await?).await?;
+        // writing twice registers the same mapping twice
+        for _ in 0..2 {
+            pipeline
+                .read_csv(&file1)
+                .await?
+                .write_delta(&local_delta_place, "tb_persistent", WriteMode::Append)
+                .await?;
+        }
+        pipeline
+            .execute_sql("CREATE VIEW answer AS SELECT 42 AS answer")
+            .await?;
+    }
+
+    let mut pipeline = Pipeline::new(DuckDB::open(&database).await?).await?;
+    pipeline
+        .execute_sql(
+            "SELECT delta_path FROM delta_mapping WHERE duck_table = 'delta_tb_persistent'",
+        )
+        .await?;
+    let delta_paths = pipeline
+        .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+        .unwrap()
+        .clone();
+    assert_eq!(
+        delta_paths
+            .iter()
+            .map(|batch| batch.num_rows())
+            .sum::<usize>(),
+        1
+    );
+
+    pipeline.execute_sql("SELECT * FROM answer").await?;
+
+    Ok(())
+}
+
+/// Maps, describes, renames and removes tables through the catalog of `engine`.
+async fn check_catalog<E: Engine>(engine: E, folder_test: &str) -> Result<(), Error> {
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+    fs::create_dir(folder_test)?;
+    generate_data(&file1).await?;
+
+    let mut pipeline = Pipeline::new(engine).await?;
+    pipeline.read_csv(&file1).await?;
+    // writing twice keeps a single mapping
+    for _ in 0..2 {
+        pipeline
+            .write_delta(&local_delta_place, "tb_catalog", WriteMode::Append)
+            .await?;
+    }
+    pipeline
+        .write_delta_with_options(
+            &local_delta_place,
+            "tb_people",
+            &WriteOptions::new(WriteMode::Overwrite)
+                .partition_by(&["City"])
+                .table_alias("people"),
+        )
+        .await?;
+
+    let names: Vec<String> = pipeline
+        .list_tables()
+        .await?
+        .into_iter()
+        .map(|mapping| mapping.name)
+        .collect();
+    assert_eq!(names, vec!["delta_tb_catalog", "people"]);
+
+    let description = pipeline.describe_table("people").await?;
+    assert_eq!(
+        description.delta_path,
+        format!("{}/tb_people", local_delta_place)
+    );
+    assert_eq!(description.version, 0);
+    assert_eq!(description.partition_columns, vec!["City"]);
+    assert_eq!(description.num_files, 3);
+    assert!(description.size_bytes > 0);
+    assert!(description.schema.field_with_name("Name").is_ok());
+    assert_eq!(
+        pipeline.describe_table("delta_tb_catalog").await?.version,
+        1
+    );
+
+    assert!(matches!(
+        pipeline.rename_mapping("people", "delta_tb_catalog").await,
+        Err(Error::TableExists(_))
+    ));
+    pipeline.rename_mapping("people", "persons").await?;
+    assert_eq!(pipeline.describe_table("persons").await?.num_files, 3);
+    assert!(matches!(
+        pipeline.describe_table("people").await,
+        Err(Error::TableNotFound(_))
+    ));
+
+    pipeline.unregister_table("persons").await?;
+    assert!(matches!(
+        pipeline.unregister_table("persons").await,
+        Err(Error::TableNotFound(_))
+    ));
+    pipeline
+        .register_delta_table(&format!("{}/tb_people", local_delta_place), "people")
+        .await?;
+    assert_eq!(pipeline.list_tables().await?.len(), 2);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_catalog_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_catalog_pipeline")?;
+
+    check_catalog(DuckDB::new().await?, &format!("{}/duckdb", folder_test)).await?;
+    check_catalog(
+        DataFusionEngine::new().await?,
+        &format!("{}/datafusion", folder_test),
+    )
+    .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_storage_config_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_storage_config_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+    let local_delta_place = format!("file://{}", folder_test);
+
+    generate_data(&file1).await?;
+
+    // nothing is loaded from ~/.aws, the archive bucket lives on another endpoint
+    let storage = StorageConfig::new()
+        .use_environment(false)
+        .endpoint("http://localhost:9000")
+        .region("eu-west-1")
+        .credentials(Credentials::new("minio", "minio123"))
+        .bucket(
+            "archive",
+            StorageConfig::new()
+                .endpoint("https://s3.us-east-1.amazonaws.com")
+                .region("us-east-1")
+                .url_style(UrlStyle::VirtualHosted),
+        );
+    let duck_engine = DuckDB::new_with_storage(&storage).await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+    pipeline
+        .execute_sql("SELECT name, scope FROM duckdb_secrets() ORDER BY name")
+        .await?;
+    let secrets = pipeline
+        .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+        .unwrap()
+        .clone();
+    assert_eq!(
+        secrets.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+        2
+    );
+
+    // secrets are left out of the debug output
+    let debug = format!(
+        "{:?}",
+        storage
+            .azure(
+                AzureConfig::new()
+                    .account_name("lake")
+                    .access_key("azure-key")
+                    .sas_token("sas-token")
+            )
+            .gcs(GcsConfig::new().service_account_key("gcs-key"))
+    );
+    assert!(debug.contains("lake"));
+    for secret in ["minio123", "azure-key", "sas-token", "gcs-key"] {
+        assert!(!debug.contains(secret));
+    }
+
+    // local tables do not need any of it
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(&local_delta_place, "tb_storage", WriteMode::Append)
+        .await?;
+    assert_eq!(
+        count_delta_rows(&format!("{}/tb_storage", local_delta_place)).await?,
+        3
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_uri_normalization_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_uri_normalization_pipeline")?;
+    let folder_name = Path::new(&folder_test)
+        .file_name()
+        .unwrap()
+        .to_str()
+        .unwrap();
+    let file1 = format!("{}/file1.csv", folder_test);
+
+    generate_data(&file1).await?;
+
+    assert_eq!(
+        normalize_uri(&format!("./{0}/../{0}/", folder_name))?,
+        format!("file://{}", folder_test)
+    );
+    assert_eq!(
+        normalize_uri(&format!("file://{}", folder_test))?,
+        format!("file://{}", folder_test)
+    );
+    assert_eq!(
+        normalize_uri("~/lake")?,
+        format!("file://{}/lake", std::env::var("HOME").unwrap())
+    );
+    assert_eq!(normalize_uri(r"C:\data\lake")?, "file:///C:/data/lake");
+    assert_eq!(normalize_uri("s3://datalake/")?, "s3://datalake");
+    assert!(matches!(
+        normalize_uri("ftp://datalake"),
+        Err(Error::InvalidUri(_))
+    ));
+
+    // a relative path is written and mapped as an absolute file:// uri
+    let mut pipeline = Pipeline::new(DataFusionEngine::new().await?).await?;
+    pipeline
+        .read_csv(&file1)
+        .await?
+        .write_delta(
+            &format!("./{}", folder_name),
+            "tb_relative",
+            WriteMode::Append,
+        )
+        .await?;
+    assert!(Path::new(&format!("{}/tb_relative/_delta_log", folder_test)).exists());
+    assert_eq!(
+        pipeline
+            .describe_table("delta_tb_relative")
+            .await?
+            .delta_path,
+        format!("file://{}/tb_relative", folder_test)
+    );
+
+    // memory:// tables outlive the step that wrote them
+    pipeline
+        .write_delta("memory://lake", "tb_memory", WriteMode::Append)
+        .await?;
+    pipeline.read_delta("memory://lake/tb_memory").await?;
+    assert_eq!(
+        pipeline
+            .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+            .unwrap()
+            .iter()
+            .map(|batch| batch.num_rows())
+            .sum::<usize>(),
+        3
+    );
+    pipeline
+        .execute_sql("SELECT * FROM delta_tb_memory WHERE \"City\" = 'New York'")
+        .await?;
+    assert_eq!(
+        pipeline
+            .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+            .unwrap()
+            .iter()
+            .map(|batch| batch.num_rows())
+            .sum::<usize>(),
+        1
+    );
+
+    // a second engine sees the same store
+    let mut other = Pipeline::new(DataFusionEngine::new().await?).await?;
+    other.read_delta("memory:///lake/tb_memory").await?;
+    other
+        .register_delta_table("memory://lake/tb_memory", "people")
+        .await?;
+    assert_eq!(other.describe_table("people").await?.version, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_azure_gcs_pipeline() -> Result<(), Error> {
+    let (_temp_dir, folder_test) = test_folder("test_azure_gcs_pipeline")?;
+    let file1 = format!("{}/file1.csv", folder_test);
+
+    generate_data(&file1).await?;
+
+    // Azurite and fake-gcs-server with a datalake container and bucket
+    let storage = StorageConfig::new()
+        .azure(AzureConfig::new().use_emulator(true))
+        .gcs(GcsConfig::new().endpoint("http://localhost:4443"));
+    let duck_engine = DuckDB::new_with_storage(&storage).await?;
+
+    let mut pipeline = Pipeline::new(duck_engine).await?;
+    pipeline
+        .execute_sql("SELECT name FROM duckdb_secrets() ORDER BY name")
+        .await?;
+    let secrets = pipeline
+        .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+        .unwrap()
+        .clone();
+    assert_eq!(
+        secrets.iter().map(|batch| batch.num_rows()).sum::<usize>(),
+        3
+    );
+
+    for delta_place in ["az://datalake", "gs://datalake"] {
+        pipeline
+            .read_csv(&file1)
+            .await?
+            .write_delta(delta_place, "tb_people", WriteMode::Overwrite)
+            .await?;
+
+        let metrics = pipeline
+            .read_csv(&file1)
+            .await?
+            .merge_update(delta_place, "tb_people", "Name", &["Age"])
+            .await?;
+        assert_eq!(metrics.num_target_rows_updated, 3);
+
+        pipeline
+            .read_delta(&format!("{}/tb_people", delta_place))
+            .await?;
+        assert_eq!(
+            pipeline
+                .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+                .unwrap()
+                .iter()
+                .map(|batch| batch.num_rows())
+                .sum::<usize>(),
+            3
+        );
+    }
+
+    // DuckDB reads the tables through mappings, with the secrets of the emulators
+    for (delta_path, table_alias) in [
+        ("az://datalake/tb_people", "az_people"),
+        ("gs://datalake/tb_people", "gs_people"),
+    ] {
+        pipeline
+            .register_delta_table(delta_path, table_alias)
+            .await?
+            .execute_sql(&format!("SELECT * FROM {}", table_alias))
+            .await?;
+        assert_eq!(
+            pipeline
+                .get_dataset(duckdelta::pipeline::DEFAULT_DATASET)
+                .unwrap()
+                .iter()
+                .map(|batch| batch.num_rows())
+                .sum::<usize>(),
+            3
+        );
+    }
+
+    Ok(())
+}